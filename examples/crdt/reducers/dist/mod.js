@@ -3772,7 +3772,7 @@ function processTxOutput(txOuput, addressType, action) {
 }
 function processBlock(blockJson, config, method) {
   const block = Block.fromJson(blockJson);
-  const addressType = config.addressType;
+  const addressType = config.address_type;
   const prefix = config.prefix;
   const deltas = {};
   for (const tx of block.body?.tx ?? []) {
@@ -69,7 +69,7 @@ function processBlock(
   method: Method,
 ) {
   const block = UtxoRpc.Block.fromJson(blockJson);
-  const addressType = config.addressType
+  const addressType = config.address_type
   const prefix = config.prefix
 
   const deltas: Record<string, bigint> = {};
@@ -1,26 +1,9 @@
 import * as reducers from './reducers/dist/mod.js'
 
-const config = [
-  {
-    name: "BalanceByAddress",
-    config: {
-      addressType: "payment",
-      table: "balance_by_address",
-    }
-  },
-  {
-    name: "BalanceByAddress",
-    config: {
-      addressType: "stake",
-      table: "balance_by_stake_address",
-    }
-  },
-]
-
-export function apply(blockJson) {
-  return reducers.apply(blockJson, config)
+export function apply(blockJson, config) {
+  return reducers.apply(blockJson, config.reducers)
 }
 
-export function undo(blockJson) {
-  return reducers.undo(blockJson, config)
+export function undo(blockJson, config) {
+  return reducers.undo(blockJson, config.reducers)
 }
\ No newline at end of file
@@ -1,9 +1,9 @@
 use gasket::runtime::Tether;
 use serde::Deserialize;
-use std::{collections::VecDeque, time::Duration};
+use std::time::Duration;
 use tracing::{info, warn};
 
-use scrolls::{framework::*, reduce, source, storage};
+use scrolls::{enrich, framework::*, reduce, source, storage};
 
 use crate::console;
 
@@ -11,10 +11,12 @@ use crate::console;
 pub struct ConfigRoot {
     intersect: IntersectConfig,
     source: source::Config,
+    enrich: Option<enrich::Config>,
     reduce: reduce::Config,
     storage: storage::Config,
     chain: Option<ChainConfig>,
     finalize: Option<FinalizeConfig>,
+    cursor: Option<CursorConfig>,
     retries: Option<gasket::retries::Policy>,
 }
 
@@ -42,6 +44,7 @@ impl ConfigRoot {
 
 struct Runtime {
     source: Tether,
+    enrich: Option<Tether>,
     reduce: Tether,
     storage: Tether,
 }
@@ -49,6 +52,7 @@ struct Runtime {
 impl Runtime {
     fn all_tethers(&self) -> impl Iterator<Item = &Tether> {
         std::iter::once(&self.source)
+            .chain(self.enrich.iter())
             .chain(std::iter::once(&self.reduce))
             .chain(std::iter::once(&self.storage))
     }
@@ -100,11 +104,23 @@ fn define_gasket_policy(config: Option<&gasket::retries::Policy>) -> gasket::run
 
 fn chain_stages<'a>(
     source: &'a mut dyn StageBootstrapper<ChainEvent, ChainEvent>,
+    enrich: Option<&'a mut dyn StageBootstrapper<ChainEvent, ChainEvent>>,
     reduce: &'a mut dyn StageBootstrapper<ChainEvent, StorageEvent>,
     storage: &'a mut dyn StageBootstrapper<StorageEvent, StorageEvent>,
 ) {
     let (to_process, from_source) = gasket::messaging::tokio::mpsc_channel(1000);
     source.connect_output(to_process);
+
+    let from_source = match enrich {
+        Some(enrich) => {
+            enrich.connect_input(from_source);
+            let (to_reduce, from_enrich) = gasket::messaging::tokio::mpsc_channel(1000);
+            enrich.connect_output(to_reduce);
+            from_enrich
+        }
+        None => from_source,
+    };
+
     reduce.connect_input(from_source);
 
     let (to_storage, from_process) = gasket::messaging::tokio::mpsc_channel(1000);
@@ -114,14 +130,23 @@ fn chain_stages<'a>(
 
 fn bootstrap(
     mut source: source::Bootstrapper,
+    mut enrich: Option<enrich::Bootstrapper>,
     mut reduce: reduce::Bootstrapper,
     mut storage: storage::Bootstrapper,
     policy: gasket::runtime::Policy,
 ) -> Result<Runtime, Error> {
-    chain_stages(&mut source, &mut reduce, &mut storage);
+    chain_stages(
+        &mut source,
+        enrich
+            .as_mut()
+            .map(|x| x as &mut dyn StageBootstrapper<ChainEvent, ChainEvent>),
+        &mut reduce,
+        &mut storage,
+    );
 
     let runtime = Runtime {
         source: source.spawn(policy.clone()),
+        enrich: enrich.map(|x| x.spawn(policy.clone())),
         reduce: reduce.spawn(policy.clone()),
         storage: storage.spawn(policy.clone()),
     };
@@ -139,8 +164,19 @@ pub fn run(args: &Args) -> Result<(), Error> {
     let finalize = config.finalize;
     let current_dir = std::env::current_dir().unwrap();
 
-    // TODO: load from persistence mechanism
-    let cursor = Cursor::new(VecDeque::new());
+    let cursor = config
+        .storage
+        .load_cursor()?
+        .with_config(&config.cursor.unwrap_or_default());
+
+    if let Some(point) = cursor.latest_known_point() {
+        info!(
+            "resuming from persisted cursor at slot {}",
+            point.slot_or_default()
+        );
+    }
+
+    let state = config.storage.state_backend();
 
     let ctx = Context {
         chain,
@@ -148,14 +184,16 @@ pub fn run(args: &Args) -> Result<(), Error> {
         finalize,
         cursor,
         current_dir,
+        state,
     };
 
     let source = config.source.bootstrapper(&ctx)?;
+    let enrich = config.enrich.map(|x| x.bootstrapper(&ctx)).transpose()?;
     let reduce = config.reduce.bootstrapper(&ctx)?;
     let storage = config.storage.bootstrapper(&ctx)?;
 
     let retries = define_gasket_policy(config.retries.as_ref());
-    let runtime = bootstrap(source, reduce, storage, retries)?;
+    let runtime = bootstrap(source, enrich, reduce, storage, retries)?;
 
     info!("scrolls is running...");
 
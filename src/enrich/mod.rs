@@ -0,0 +1,47 @@
+use gasket::{
+    messaging::{RecvPort, SendPort},
+    runtime::Tether,
+};
+use serde::Deserialize;
+
+use crate::framework::*;
+
+pub mod sled;
+
+pub enum Bootstrapper {
+    Sled(sled::Stage),
+}
+
+impl StageBootstrapper<ChainEvent, ChainEvent> for Bootstrapper {
+    fn connect_input(&mut self, adapter: InputAdapter<ChainEvent>) {
+        match self {
+            Bootstrapper::Sled(p) => p.input.connect(adapter),
+        }
+    }
+
+    fn connect_output(&mut self, adapter: OutputAdapter<ChainEvent>) {
+        match self {
+            Bootstrapper::Sled(p) => p.output.connect(adapter),
+        }
+    }
+
+    fn spawn(self, policy: gasket::runtime::Policy) -> Tether {
+        match self {
+            Bootstrapper::Sled(x) => gasket::runtime::spawn_stage(x, policy),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum Config {
+    Sled(sled::Config),
+}
+
+impl Config {
+    pub fn bootstrapper(self, ctx: &Context) -> Result<Bootstrapper, Error> {
+        match self {
+            Config::Sled(c) => Ok(Bootstrapper::Sled(c.bootstrapper(ctx)?)),
+        }
+    }
+}
@@ -0,0 +1,393 @@
+use gasket::framework::*;
+use pallas::network::miniprotocols::Point;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tracing::{debug, warn};
+use utxorpc::proto::cardano::v1::{TxInput, TxOutput};
+
+use crate::framework::*;
+
+const UTXO_PREFIX: u8 = b'u';
+const JOURNAL_PREFIX: u8 = b'j';
+
+// 2160 blocks (k) at an active slot coefficient of 0.05
+const DEFAULT_ROLLBACK_WINDOW: u64 = 43200;
+
+fn utxo_key(tx_hash: &[u8], index: u32) -> Vec<u8> {
+    let mut key = vec![UTXO_PREFIX];
+    key.extend_from_slice(tx_hash);
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+// slots are big-endian encoded so that journal entries are sorted by slot
+fn journal_key(slot: u64, hash: &[u8]) -> Vec<u8> {
+    let mut key = vec![JOURNAL_PREFIX];
+    key.extend_from_slice(&slot.to_be_bytes());
+    key.extend_from_slice(hash);
+    key
+}
+
+fn journal_key_slot(key: &[u8]) -> u64 {
+    let mut slot = [0u8; 8];
+    slot.copy_from_slice(&key[1..9]);
+    u64::from_be_bytes(slot)
+}
+
+/// Changes applied to the UTxO set by a single block, kept so that the block
+/// can be reverted on undo or reset
+#[derive(Serialize, Deserialize, Default)]
+struct JournalEntry {
+    /// hex-encoded utxo key and encoded output of every consumed utxo
+    consumed: Vec<(String, String)>,
+    /// hex-encoded utxo key of every produced utxo
+    produced: Vec<String>,
+}
+
+impl JournalEntry {
+    fn revert(&self, batch: &mut ::sled::Batch) -> Result<(), WorkerError> {
+        // consumed utxos are restored before removing the produced ones so
+        // that outputs created and spent within the same block end up removed
+        for (key, txo) in self.consumed.iter() {
+            batch.insert(hex::decode(key).or_panic()?, hex::decode(txo).or_panic()?);
+        }
+
+        for key in self.produced.iter() {
+            batch.remove(hex::decode(key).or_panic()?);
+        }
+
+        Ok(())
+    }
+
+    fn consumed_outputs(&self) -> Result<HashMap<Vec<u8>, TxOutput>, WorkerError> {
+        let mut out = HashMap::new();
+
+        for (key, txo) in self.consumed.iter() {
+            let txo = TxOutput::decode(hex::decode(txo).or_panic()?.as_slice()).or_panic()?;
+            out.insert(hex::decode(key).or_panic()?, txo);
+        }
+
+        Ok(out)
+    }
+}
+
+/// Inputs of every tx, including its collateral
+fn block_inputs(block: &mut Block) -> impl Iterator<Item = &mut TxInput> {
+    block
+        .body
+        .iter_mut()
+        .flat_map(|x| x.tx.iter_mut())
+        .flat_map(|tx| {
+            tx.inputs.iter_mut().chain(
+                tx.collateral
+                    .iter_mut()
+                    .flat_map(|x| x.collateral.iter_mut()),
+            )
+        })
+}
+
+/// Adds an output produced by the block to the batch and the journal
+fn produce(
+    key: Vec<u8>,
+    txo: &TxOutput,
+    batch: &mut ::sled::Batch,
+    journal: &mut JournalEntry,
+    produced: &mut HashMap<Vec<u8>, TxOutput>,
+) {
+    batch.insert(key.clone(), txo.encode_to_vec());
+    journal.produced.push(hex::encode(&key));
+    produced.insert(key, txo.clone());
+}
+
+pub struct Worker {
+    db: ::sled::Db,
+}
+
+impl Worker {
+    fn load_utxo(&self, key: &[u8]) -> Result<Option<TxOutput>, WorkerError> {
+        match self.db.get(key).or_restart()? {
+            Some(bytes) => Ok(Some(TxOutput::decode(bytes.as_ref()).or_panic()?)),
+            None => Ok(None),
+        }
+    }
+
+    fn load_journal(&self, key: &[u8]) -> Result<Option<JournalEntry>, WorkerError> {
+        match self.db.get(key).or_restart()? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes).or_panic()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Fills the resolved output of every input from the outputs consumed
+    /// by the block, falling back to the db for inputs it didn't consume
+    fn fill_inputs(
+        &self,
+        block: &mut Block,
+        consumed: &HashMap<Vec<u8>, TxOutput>,
+    ) -> Result<(), WorkerError> {
+        for txi in block_inputs(block) {
+            let key = utxo_key(&txi.tx_hash, txi.output_index);
+
+            txi.as_output = match consumed.get(&key) {
+                Some(x) => Some(x.clone()),
+                None => self.load_utxo(&key)?,
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the utxo spent by the input, looking first at the outputs
+    /// produced earlier in the block, and fills its output
+    fn resolve(
+        &self,
+        txi: &mut TxInput,
+        produced: &HashMap<Vec<u8>, TxOutput>,
+    ) -> Result<Option<Vec<u8>>, WorkerError> {
+        let key = utxo_key(&txi.tx_hash, txi.output_index);
+
+        let txo = match produced.get(&key) {
+            Some(x) => Some(x.clone()),
+            None => self.load_utxo(&key)?,
+        };
+
+        if txo.is_none() {
+            warn!(
+                "missing utxo {}#{}",
+                hex::encode(&txi.tx_hash),
+                txi.output_index
+            );
+        }
+
+        txi.as_output = txo;
+
+        Ok(txi.as_output.as_ref().map(|_| key))
+    }
+
+    /// Resolves the utxo spent by the input and removes it from the set
+    fn consume(
+        &self,
+        txi: &mut TxInput,
+        batch: &mut ::sled::Batch,
+        journal: &mut JournalEntry,
+        produced: &mut HashMap<Vec<u8>, TxOutput>,
+    ) -> Result<(), WorkerError> {
+        if let Some(key) = self.resolve(txi, produced)? {
+            let txo = txi.as_output.as_ref().unwrap();
+
+            batch.remove(key.clone());
+            journal
+                .consumed
+                .push((hex::encode(&key), hex::encode(txo.encode_to_vec())));
+            produced.remove(&key);
+        }
+
+        Ok(())
+    }
+
+    fn apply_block(
+        &mut self,
+        point: &Point,
+        block: &mut Block,
+        rollback_window: u64,
+    ) -> Result<(), WorkerError> {
+        let (slot, hash) = match point {
+            Point::Specific(slot, hash) => (*slot, hash),
+            Point::Origin => return Ok(()),
+        };
+
+        // a block that was already applied, e.g. replayed after a restart, is
+        // enriched from its journal entry since its inputs are already spent
+        if let Some(journal) = self.load_journal(&journal_key(slot, hash))? {
+            debug!("block at slot {} was already applied", slot);
+            return self.fill_inputs(block, &journal.consumed_outputs()?);
+        }
+
+        let mut batch = ::sled::Batch::default();
+        let mut journal = JournalEntry::default();
+
+        // outputs produced by this block are not yet in the db
+        let mut produced = HashMap::new();
+
+        for tx in block.body.iter_mut().flat_map(|x| x.tx.iter_mut()) {
+            if tx.successful {
+                for txi in tx.inputs.iter_mut() {
+                    self.consume(txi, &mut batch, &mut journal, &mut produced)?;
+                }
+
+                for (index, txo) in tx.outputs.iter().enumerate() {
+                    let key = utxo_key(&tx.hash, index as u32);
+                    produce(key, txo, &mut batch, &mut journal, &mut produced);
+                }
+            } else {
+                // failed txs leave their inputs unspent and consume the
+                // collateral instead, producing the collateral return (if
+                // any) at the index following the regular outputs
+                for txi in tx.inputs.iter_mut() {
+                    self.resolve(txi, &produced)?;
+                }
+
+                if let Some(collateral) = tx.collateral.as_mut() {
+                    for txi in collateral.collateral.iter_mut() {
+                        self.consume(txi, &mut batch, &mut journal, &mut produced)?;
+                    }
+
+                    if let Some(txo) = &collateral.collateral_return {
+                        let key = utxo_key(&tx.hash, tx.outputs.len() as u32);
+                        produce(key, txo, &mut batch, &mut journal, &mut produced);
+                    }
+                }
+            }
+        }
+
+        let journal = serde_json::to_vec(&journal).or_panic()?;
+        batch.insert(journal_key(slot, hash), journal);
+
+        // entries past the rollback window can't be undone anymore
+        let horizon = journal_key(slot.saturating_sub(rollback_window), &[]);
+        for entry in self.db.range(vec![JOURNAL_PREFIX]..horizon) {
+            let (key, _) = entry.or_restart()?;
+            batch.remove(key);
+        }
+
+        self.db.apply_batch(batch).or_restart()?;
+
+        Ok(())
+    }
+
+    fn undo_block(&mut self, point: &Point, block: &mut Block) -> Result<(), WorkerError> {
+        let key = match point {
+            Point::Specific(slot, hash) => journal_key(*slot, hash),
+            Point::Origin => return Ok(()),
+        };
+
+        let journal = match self.load_journal(&key)? {
+            Some(x) => x,
+            None => {
+                warn!(
+                    "no journal entry to undo block at slot {}",
+                    point.slot_or_default()
+                );
+                return Ok(());
+            }
+        };
+
+        self.fill_inputs(block, &journal.consumed_outputs()?)?;
+
+        let mut batch = ::sled::Batch::default();
+        journal.revert(&mut batch)?;
+        batch.remove(key);
+
+        self.db.apply_batch(batch).or_restart()?;
+
+        Ok(())
+    }
+
+    /// Reverts every journaled block after the given point, newest first
+    fn rollback_to(&mut self, point: &Point) -> Result<(), WorkerError> {
+        let slot = point.slot_or_default();
+
+        let mut batch = ::sled::Batch::default();
+
+        for entry in self.db.scan_prefix([JOURNAL_PREFIX]).rev() {
+            let (key, value) = entry.or_restart()?;
+
+            if journal_key_slot(&key) <= slot {
+                break;
+            }
+
+            debug!("reverting block at slot {}", journal_key_slot(&key));
+
+            let journal: JournalEntry = serde_json::from_slice(&value).or_panic()?;
+            journal.revert(&mut batch)?;
+            batch.remove(key);
+        }
+
+        self.db.apply_batch(batch).or_restart()?;
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl gasket::framework::Worker<Stage> for Worker {
+    async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
+        let db = ::sled::open(&stage.path).or_panic()?;
+
+        Ok(Self { db })
+    }
+
+    async fn schedule(
+        &mut self,
+        stage: &mut Stage,
+    ) -> Result<WorkSchedule<ChainEvent>, WorkerError> {
+        let msg = stage.input.recv().await.or_panic()?;
+
+        Ok(WorkSchedule::Unit(msg.payload))
+    }
+
+    async fn execute(&mut self, unit: &ChainEvent, stage: &mut Stage) -> Result<(), WorkerError> {
+        let unit = unit
+            .clone()
+            .try_map_record(Record::into_parsed)
+            .or_panic()?;
+
+        let evt = match unit {
+            ChainEvent::Apply(point, Record::ParsedBlock(mut block)) => {
+                self.apply_block(&point, &mut block, stage.rollback_window)?;
+                ChainEvent::Apply(point, Record::ParsedBlock(block))
+            }
+            ChainEvent::Undo(point, Record::ParsedBlock(mut block)) => {
+                self.undo_block(&point, &mut block)?;
+                ChainEvent::Undo(point, Record::ParsedBlock(block))
+            }
+            ChainEvent::Reset(point) => {
+                self.rollback_to(&point)?;
+                ChainEvent::Reset(point)
+            }
+            x => x,
+        };
+
+        stage.output.send(evt.into()).await.or_panic()?;
+        stage.ops_count.inc(1);
+
+        Ok(())
+    }
+}
+
+#[derive(Stage)]
+#[stage(name = "enrich-sled", unit = "ChainEvent", worker = "Worker")]
+pub struct Stage {
+    path: PathBuf,
+    rollback_window: u64,
+
+    pub input: EnrichInputPort,
+    pub output: EnrichOutputPort,
+
+    #[metric]
+    ops_count: gasket::metrics::Counter,
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    /// directory where the utxo set is persisted
+    path: String,
+    /// amount of slots for which blocks can still be reverted
+    rollback_window: Option<u64>,
+}
+
+impl Config {
+    pub fn bootstrapper(self, ctx: &Context) -> Result<Stage, Error> {
+        let stage = Stage {
+            path: ctx.current_dir.join(self.path),
+            rollback_window: self.rollback_window.unwrap_or(DEFAULT_ROLLBACK_WINDOW),
+            input: Default::default(),
+            output: Default::default(),
+            ops_count: Default::default(),
+        };
+
+        Ok(stage)
+    }
+}
@@ -1,45 +1,304 @@
 use std::{
     collections::VecDeque,
     sync::{Arc, RwLock},
+    time::Duration,
 };
 
 use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+use tokio::sync::Notify;
 
-const HARDCODED_BREADCRUMBS: usize = 20;
+use super::errors::Error;
+use super::BlockDirection;
+
+const DEFAULT_DENSITY: usize = 10;
 
 type State = VecDeque<Point>;
 
-// TODO: include exponential breadcrumbs logic here
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct CursorConfig {
+    /// amount of breadcrumbs kept for the most recent blocks and for each
+    /// exponentially growing distance bucket behind them
+    pub density: Option<usize>,
+}
+
+/// Keeps track of the points processed so far as a list of breadcrumbs,
+/// newest first. The most recent blocks are kept densely while older ones are
+/// thinned out so that their distance to the tip grows exponentially (roughly
+/// 1, 2, 4, 8... slots back), allowing to find an intersection even after a
+/// deep rollback without storing the whole history.
 #[derive(Clone)]
-pub struct Cursor(Arc<RwLock<State>>);
+pub struct Cursor {
+    state: Arc<RwLock<State>>,
+    density: usize,
+    // notified whenever the breadcrumbs change
+    changed: Arc<Notify>,
+}
 
 impl Cursor {
     pub fn new(state: State) -> Self {
-        Self(Arc::new(RwLock::new(state)))
+        Self {
+            state: Arc::new(RwLock::new(state)),
+            density: DEFAULT_DENSITY,
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn with_config(self, config: &CursorConfig) -> Self {
+        Self {
+            density: config.density.unwrap_or(DEFAULT_DENSITY).max(1),
+            ..self
+        }
+    }
+
+    /// Restores a cursor from the json representation produced by `to_json`
+    pub fn from_json(value: &str) -> Result<Self, Error> {
+        let breadcrumbs: Vec<(u64, String)> = serde_json::from_str(value).map_err(Error::parse)?;
+
+        let state = breadcrumbs
+            .into_iter()
+            .map(|(slot, hash)| {
+                hex::decode(hash)
+                    .map(|hash| Point::Specific(slot, hash))
+                    .map_err(Error::parse)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self::new(state))
+    }
+
+    /// Serializes the breadcrumbs as a json list of `[slot, hash]` pairs, the
+    /// same shape used by the `Breadcrumbs` intersect config
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.breadcrumbs()).unwrap()
+    }
+
+    pub fn breadcrumbs(&self) -> Vec<(u64, String)> {
+        let state = self.state.read().unwrap();
+
+        state
+            .iter()
+            .filter_map(|point| match point {
+                Point::Specific(slot, hash) => Some((*slot, hex::encode(hash))),
+                Point::Origin => None,
+            })
+            .collect()
+    }
+
+    /// Creates a detached copy that can be modified without affecting the
+    /// shared state, useful to persist the next state before committing it
+    pub fn fork(&self) -> Self {
+        Self {
+            state: Arc::new(RwLock::new(self.clone_state())),
+            density: self.density,
+            changed: Arc::new(Notify::new()),
+        }
     }
 
     pub fn is_empty(&self) -> bool {
-        let v = self.0.read().unwrap();
+        let v = self.state.read().unwrap();
         v.is_empty()
     }
 
     pub fn clone_state(&self) -> State {
-        let v = self.0.read().unwrap();
+        let v = self.state.read().unwrap();
         v.clone()
     }
 
     pub fn latest_known_point(&self) -> Option<Point> {
-        let state = self.0.read().unwrap();
+        let state = self.state.read().unwrap();
         state.front().cloned()
     }
 
+    /// Whether the point is the latest tracked block, which is the case when
+    /// a block that was already committed is sent again, e.g. by a source
+    /// resuming from an inclusive start point
+    pub fn is_latest(&self, value: &Point) -> bool {
+        let state = self.state.read().unwrap();
+        state.front() == Some(value)
+    }
+
+    /// Waits until the latest known point satisfies the condition, returning
+    /// false if it still doesn't once the timeout elapses
+    pub async fn wait_until(
+        &self,
+        timeout: Duration,
+        condition: impl Fn(Option<Point>) -> bool,
+    ) -> bool {
+        let wait = async {
+            loop {
+                // created before checking so that a change in between isn't
+                // missed
+                let changed = self.changed.notified();
+
+                if condition(self.latest_known_point()) {
+                    return;
+                }
+
+                changed.await;
+            }
+        };
+
+        tokio::time::timeout(timeout, wait).await.is_ok()
+    }
+
     pub fn add_breadcrumb(&self, value: Point) {
-        let mut state = self.0.write().unwrap();
+        let mut state = self.state.write().unwrap();
 
         state.push_front(value);
 
-        if state.len() > HARDCODED_BREADCRUMBS {
-            state.pop_back();
+        prune(&mut state, self.density);
+
+        self.changed.notify_waiters();
+    }
+
+    /// Drops the breadcrumb of the undone block along with any after it.
+    /// Blocks sharing its slot (e.g. a Byron epoch boundary block and the one
+    /// following it) are kept unless they are newer.
+    pub fn undo_breadcrumb(&self, value: &Point) {
+        let mut state = self.state.write().unwrap();
+
+        let slot = value.slot_or_default();
+
+        while matches!(state.front(), Some(x) if x.slot_or_default() > slot) {
+            state.pop_front();
         }
+
+        if state.front() == Some(value) {
+            state.pop_front();
+        }
+
+        self.changed.notify_waiters();
+    }
+
+    /// Drops the breadcrumbs of every block after the given point
+    pub fn rollback_to(&self, value: &Point) {
+        let mut state = self.state.write().unwrap();
+
+        let slot = value.slot_or_default();
+
+        while matches!(state.front(), Some(x) if x.slot_or_default() > slot) {
+            state.pop_front();
+        }
+
+        self.changed.notify_waiters();
+    }
+
+    /// Tracks a block that finished processing in the given direction. A
+    /// block applied again right after itself is only tracked once.
+    pub fn track_block(&self, value: Point, direction: BlockDirection) {
+        match direction {
+            BlockDirection::Apply if self.is_latest(&value) => (),
+            BlockDirection::Apply => self.add_breadcrumb(value),
+            BlockDirection::Undo => self.undo_breadcrumb(&value),
+        }
+    }
+}
+
+/// Thins out the breadcrumbs by keeping the first `density` points as-is and
+/// then, for each bucket of points whose distance to the tip falls in the same
+/// power of two, keeping only the `density` oldest ones. Keeping the oldest
+/// ensures that points keep flowing into the deeper buckets as the tip moves.
+fn prune(state: &mut State, density: usize) {
+    let tip = match state.front() {
+        Some(x) => x.slot_or_default(),
+        None => return,
+    };
+
+    let mut buckets = [0usize; 65];
+    let mut kept = State::with_capacity(state.len());
+
+    for (i, point) in state.iter().enumerate().rev() {
+        if i >= density {
+            let distance = tip.saturating_sub(point.slot_or_default());
+            let bucket = (u64::BITS - distance.leading_zeros()) as usize;
+
+            if buckets[bucket] >= density {
+                continue;
+            }
+
+            buckets[bucket] += 1;
+        }
+
+        kept.push_front(point.clone());
+    }
+
+    *state = kept;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(density: usize) -> Cursor {
+        let config = CursorConfig {
+            density: Some(density),
+        };
+
+        Cursor::new(State::new()).with_config(&config)
+    }
+
+    fn track(cursor: &Cursor, slots: impl IntoIterator<Item = u64>) {
+        for slot in slots {
+            cursor.add_breadcrumb(Point::Specific(slot, slot.to_be_bytes().to_vec()));
+        }
+    }
+
+    fn slots(cursor: &Cursor) -> Vec<u64> {
+        cursor.breadcrumbs().into_iter().map(|(x, _)| x).collect()
+    }
+
+    fn bucket(distance: u64) -> usize {
+        (u64::BITS - distance.leading_zeros()) as usize
+    }
+
+    #[test]
+    fn keeps_newest_points() {
+        let cursor = cursor(10);
+        track(&cursor, 1..=5000);
+
+        let slots = slots(&cursor);
+
+        assert_eq!(slots[..10], (4991..=5000).rev().collect::<Vec<_>>());
+        assert!(slots.windows(2).all(|x| x[0] > x[1]));
+    }
+
+    #[test]
+    fn size_is_bounded() {
+        for density in [1, 3, 10] {
+            let cursor = cursor(density);
+
+            for tip in 1..=5000 {
+                track(&cursor, [tip]);
+
+                // the newest points plus at most `density` per bucket
+                let buckets = bucket(tip) + 1;
+                assert!(cursor.breadcrumbs().len() <= density * (buckets + 1));
+            }
+        }
+    }
+
+    #[test]
+    fn spacing_grows_with_depth() {
+        let cursor = cursor(10);
+        track(&cursor, 1..=5000);
+
+        let slots = slots(&cursor);
+        let tip = slots[0];
+        let oldest = slots[slots.len() - 1];
+
+        let mut buckets = [0usize; 65];
+
+        for slot in slots.iter().skip(10) {
+            buckets[bucket(tip - slot)] += 1;
+        }
+
+        assert!(buckets.iter().all(|x| *x <= 10));
+
+        // the older half spans way more slots than the newer one, reaching
+        // back close to the origin
+        let middle = slots[slots.len() / 2];
+        assert!(middle - oldest > 4 * (tip - middle));
+        assert!(tip - oldest > tip / 2);
     }
 }
@@ -0,0 +1,194 @@
+//! Mapping of raw CBOR blocks into our canonical UtxoRpc representation
+//!
+//! Only a subset of the UtxoRpc block is filled: the header slot, hash and
+//! height; tx inputs, outputs, reference inputs, collateral, mint, fee,
+//! withdrawals, validity interval and vkey witnesses; and the output
+//! address, coin, assets and datum hash. Certificates, auxiliary data,
+//! scripts, redeemers, plutus data and inline datum values are left empty,
+//! which is logged once when a block carrying them is decoded.
+
+use std::sync::Once;
+
+use pallas::crypto::hash::Hasher;
+use pallas::ledger::primitives::babbage::DatumOption;
+use pallas::ledger::traverse::{
+    MultiEraAsset, MultiEraBlock, MultiEraInput, MultiEraOutput, MultiEraPolicyAssets, MultiEraTx,
+};
+use utxorpc::proto::cardano::v1::{
+    Asset, BlockBody, BlockHeader, Collateral, Multiasset, TxInput, TxOutput, TxValidity,
+    VKeyWitness, Withdrawal, WitnessSet,
+};
+
+use super::{Block, Error, Record, Tx};
+
+static UNSUPPORTED_WARNING: Once = Once::new();
+
+fn warn_unsupported(what: &str) {
+    UNSUPPORTED_WARNING.call_once(|| {
+        tracing::warn!(
+            what,
+            "raw blocks carry data that isn't mapped into the parsed block, see the README"
+        );
+    });
+}
+
+fn map_asset(asset: &MultiEraAsset) -> Asset {
+    Asset {
+        name: asset.name().to_vec().into(),
+        output_coin: asset.output_coin().unwrap_or_default(),
+        mint_coin: asset.mint_coin().unwrap_or_default(),
+        ..Default::default()
+    }
+}
+
+fn map_policy_assets(policy: &MultiEraPolicyAssets) -> Multiasset {
+    Multiasset {
+        policy_id: policy.policy().to_vec().into(),
+        assets: policy.assets().iter().map(map_asset).collect(),
+        ..Default::default()
+    }
+}
+
+fn map_input(input: &MultiEraInput) -> TxInput {
+    TxInput {
+        tx_hash: input.hash().to_vec().into(),
+        output_index: input.index() as u32,
+        ..Default::default()
+    }
+}
+
+fn map_output(output: &MultiEraOutput) -> Result<TxOutput, Error> {
+    let address = output.address().map_err(Error::parse)?;
+
+    let datum_hash = match output.datum() {
+        Some(DatumOption::Hash(x)) => x.to_vec(),
+        Some(DatumOption::Data(x)) => {
+            warn_unsupported("inline datum");
+            Hasher::<256>::hash_cbor(&x.0).to_vec()
+        }
+        None => vec![],
+    };
+
+    let out = TxOutput {
+        address: address.to_vec().into(),
+        coin: output.lovelace_amount(),
+        assets: output
+            .non_ada_assets()
+            .iter()
+            .map(map_policy_assets)
+            .collect(),
+        datum_hash: datum_hash.into(),
+        ..Default::default()
+    };
+
+    Ok(out)
+}
+
+fn map_withdrawals(tx: &MultiEraTx) -> Vec<Withdrawal> {
+    tx.withdrawals()
+        .collect::<Vec<(&[u8], u64)>>()
+        .into_iter()
+        .map(|(account, coin)| Withdrawal {
+            reward_account: account.to_vec().into(),
+            coin,
+            ..Default::default()
+        })
+        .collect()
+}
+
+fn map_witnesses(tx: &MultiEraTx) -> WitnessSet {
+    WitnessSet {
+        vkeywitness: tx
+            .vkey_witnesses()
+            .iter()
+            .map(|x| VKeyWitness {
+                vkey: x.vkey.to_vec().into(),
+                signature: x.signature.to_vec().into(),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn map_collateral(tx: &MultiEraTx) -> Result<Collateral, Error> {
+    let out = Collateral {
+        collateral: tx.collateral().iter().map(map_input).collect(),
+        collateral_return: tx
+            .collateral_return()
+            .as_ref()
+            .map(map_output)
+            .transpose()?,
+        total_collateral: tx.total_collateral().unwrap_or_default(),
+        ..Default::default()
+    };
+
+    Ok(out)
+}
+
+fn map_tx(tx: &MultiEraTx) -> Result<Tx, Error> {
+    if !tx.certs().is_empty() {
+        warn_unsupported("certificates");
+    }
+
+    let out = Tx {
+        hash: tx.hash().to_vec().into(),
+        inputs: tx.inputs().iter().map(map_input).collect(),
+        outputs: tx
+            .outputs()
+            .iter()
+            .map(map_output)
+            .collect::<Result<_, _>>()?,
+        reference_inputs: tx.reference_inputs().iter().map(map_input).collect(),
+        mint: tx.mints().iter().map(map_policy_assets).collect(),
+        fee: tx.fee().unwrap_or_default(),
+        collateral: Some(map_collateral(tx)?),
+        withdrawals: map_withdrawals(tx),
+        witnesses: Some(map_witnesses(tx)),
+        validity: Some(TxValidity {
+            start: tx.validity_start().unwrap_or_default(),
+            ttl: tx.ttl().unwrap_or_default(),
+            ..Default::default()
+        }),
+        successful: tx.is_valid(),
+        ..Default::default()
+    };
+
+    Ok(out)
+}
+
+/// Decodes a block of any era and maps it into a UtxoRpc `Block`
+pub fn parse_block(cbor: &[u8]) -> Result<Block, Error> {
+    let block = MultiEraBlock::decode(cbor).map_err(Error::parse)?;
+
+    let header = BlockHeader {
+        slot: block.slot(),
+        hash: block.hash().to_vec().into(),
+        height: block.number(),
+        ..Default::default()
+    };
+
+    let body = BlockBody {
+        tx: block.txs().iter().map(map_tx).collect::<Result<_, _>>()?,
+        ..Default::default()
+    };
+
+    let out = Block {
+        header: Some(header),
+        body: Some(body),
+        ..Default::default()
+    };
+
+    Ok(out)
+}
+
+impl Record {
+    /// Decodes `CborBlock` records into `ParsedBlock`, leaving any other
+    /// variant untouched
+    pub fn into_parsed(self) -> Result<Record, Error> {
+        match self {
+            Record::CborBlock(cbor) => Ok(Record::ParsedBlock(parse_block(&cbor)?)),
+            x => Ok(x),
+        }
+    }
+}
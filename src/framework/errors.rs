@@ -1,3 +1,4 @@
+use pallas::network::miniprotocols::Point;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -10,6 +11,17 @@ pub enum Error {
 
     #[error("parse error {0}")]
     Parse(String),
+
+    #[error("storage error {0}")]
+    Storage(String),
+
+    #[error("reducer {method} failed at slot {slot}, block {hash}: {message}")]
+    Reducer {
+        method: String,
+        slot: u64,
+        hash: String,
+        message: String,
+    },
 }
 
 impl Error {
@@ -24,4 +36,43 @@ impl Error {
     pub fn parse(error: impl ToString) -> Self {
         Self::Parse(error.to_string())
     }
+
+    pub fn storage(error: impl ToString) -> Self {
+        Self::Storage(error.to_string())
+    }
+
+    pub fn reducer(method: &str, point: &Point, message: impl ToString) -> Self {
+        let (slot, hash) = match point {
+            Point::Specific(slot, hash) => (*slot, hex::encode(hash)),
+            Point::Origin => (0, String::from("origin")),
+        };
+
+        Self::Reducer {
+            method: method.to_string(),
+            slot,
+            hash,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// A reducer output item that couldn't be decoded into a storage command
+#[derive(Error, Debug)]
+#[error("malformed item {index} of reducer output: {reason}")]
+pub struct CommandError {
+    /// position of the item within the reducer output
+    pub index: usize,
+    /// the item as emitted by the reducer
+    pub item: serde_json::Value,
+    pub reason: String,
+}
+
+impl CommandError {
+    pub fn new(index: usize, item: serde_json::Value, reason: impl ToString) -> Self {
+        Self {
+            index,
+            item,
+            reason: reason.to_string(),
+        }
+    }
 }
@@ -1,7 +1,8 @@
 //! Internal pipeline framework
 
 use pallas::network::miniprotocols::Point;
-use serde::Deserialize;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt::Debug;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -14,6 +15,7 @@ pub use utxorpc::proto::cardano::v1::Tx;
 pub use pallas::ledger::traverse::wellknown::GenesisValues;
 
 pub mod cursor;
+pub mod decode;
 pub mod errors;
 
 pub use cursor::*;
@@ -47,12 +49,21 @@ impl From<ChainConfig> for GenesisValues {
     }
 }
 
+/// Storage backend holding the state persisted by the storage stage, which
+/// reducers can perform read-only lookups against
+#[derive(Clone, Debug)]
+pub enum StateBackend {
+    Redis(String),
+    Postgres(String),
+}
+
 pub struct Context {
     pub chain: ChainConfig,
     pub intersect: IntersectConfig,
     pub cursor: Cursor,
     pub finalize: Option<FinalizeConfig>,
     pub current_dir: PathBuf,
+    pub state: Option<StateBackend>,
 }
 
 use serde_json::{json, Value as JsonValue};
@@ -194,6 +205,26 @@ impl From<ChainEvent> for JsonValue {
     }
 }
 
+/// Whether the commands of a block apply it or revert a previous apply, so
+/// that storage can track its breadcrumbs accordingly
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockDirection {
+    #[default]
+    Apply,
+    Undo,
+}
+
+impl BlockDirection {
+    /// Name of the reducer method handling blocks in this direction
+    pub fn method(&self) -> &'static str {
+        match self {
+            BlockDirection::Apply => "apply",
+            BlockDirection::Undo => "undo",
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum StorageEvent {
     CRDT(CRDTCommand),
@@ -205,6 +236,8 @@ pub type Member = String;
 pub type Key = String;
 pub type Delta = i64;
 pub type Timestamp = u64;
+pub type Table = String;
+pub type Column = String;
 
 #[derive(Clone, Debug)]
 pub enum Value {
@@ -214,6 +247,71 @@ pub enum Value {
     Json(serde_json::Value),
 }
 
+impl Value {
+    /// Decodes a reducer value. Tagged objects such as
+    /// `{"type":"bigint","value":"123"}` or `{"type":"cbor","hex":"..."}` map
+    /// to the corresponding variant, anything else is kept as `Value::Json`.
+    pub fn from_json(value: &JsonValue) -> Result<Value, String> {
+        let obj = match value.as_object() {
+            Some(x) => x,
+            None => return Ok(Value::Json(value.clone())),
+        };
+
+        match obj.get("type").and_then(JsonValue::as_str) {
+            Some("string") => Ok(Value::String(extract_string(obj, "value")?)),
+            Some("bigint") => {
+                // big integers are usually sent as strings since they can't be
+                // represented as a JS number without losing precision
+                let value = match obj.get("value") {
+                    Some(JsonValue::String(s)) => s.clone(),
+                    Some(JsonValue::Number(n)) => n.to_string(),
+                    _ => {
+                        return Err(
+                            "Expected an integer or stringified integer for key value".into()
+                        )
+                    }
+                };
+
+                i128::from_str(&value)
+                    .map(Value::BigInt)
+                    .map_err(|_| format!("Failed to parse big integer {}", value))
+            }
+            Some("cbor") => {
+                let encoded = extract_string(obj, "hex")?;
+                let bytes = hex::decode(encoded).map_err(|_| "Expected valid hex for key hex")?;
+                Ok(Value::Cbor(bytes))
+            }
+            Some("json") => obj
+                .get("value")
+                .cloned()
+                .map(Value::Json)
+                .ok_or_else(|| "Expected a value for key value".into()),
+            _ => Ok(Value::Json(value.clone())),
+        }
+    }
+}
+
+impl Serialize for Value {
+    // always tagged, so that the value decodes back into the same variant
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let tagged = match self {
+            Value::String(x) => json!({ "type": "string", "value": x }),
+            Value::BigInt(x) => json!({ "type": "bigint", "value": x.to_string() }),
+            Value::Cbor(x) => json!({ "type": "cbor", "hex": hex::encode(x) }),
+            Value::Json(x) => json!({ "type": "json", "value": x }),
+        };
+
+        tagged.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = JsonValue::deserialize(deserializer)?;
+        Value::from_json(&value).map_err(serde::de::Error::custom)
+    }
+}
+
 impl From<String> for Value {
     fn from(x: String) -> Self {
         Value::String(x)
@@ -232,9 +330,10 @@ impl From<serde_json::Value> for Value {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(from = "CRDTCommandJson", into = "CRDTCommandJson")]
 pub enum CRDTCommand {
-    BlockStarting(Point),
+    BlockStarting(Point, BlockDirection),
     SetAdd(Set, Member),
     SetRemove(Set, Member),
     SortedSetAdd(Set, Member, Delta),
@@ -248,14 +347,238 @@ pub enum CRDTCommand {
     HashCounter(Key, Member, Delta),
     HashSetValue(Key, Member, Value),
     HashUnsetKey(Key, Member),
-    BlockFinished(Point),
+    BlockFinished(Point, BlockDirection),
+    /// reverts every block committed after the point, sent outside of any
+    /// block when the chain is reset
+    Rollback(Point),
+}
+
+/// JSON representation of a `CRDTCommand`, tagged by the `command` key and
+/// with named fields
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "command")]
+enum CRDTCommandJson {
+    BlockStarting {
+        #[serde(with = "point_serde")]
+        point: Point,
+        #[serde(default)]
+        direction: BlockDirection,
+    },
+    SetAdd {
+        set: Set,
+        member: Member,
+    },
+    SetRemove {
+        set: Set,
+        member: Member,
+    },
+    SortedSetAdd {
+        set: Set,
+        member: Member,
+        #[serde(deserialize_with = "deserialize_delta")]
+        delta: Delta,
+    },
+    SortedSetRemove {
+        set: Set,
+        member: Member,
+        #[serde(deserialize_with = "deserialize_delta")]
+        delta: Delta,
+    },
+    TwoPhaseSetAdd {
+        set: Set,
+        member: Member,
+    },
+    TwoPhaseSetRemove {
+        set: Set,
+        member: Member,
+    },
+    GrowOnlySetAdd {
+        set: Set,
+        member: Member,
+    },
+    LastWriteWins {
+        key: Key,
+        value: Value,
+        timestamp: Timestamp,
+    },
+    AnyWriteWins {
+        key: Key,
+        value: Value,
+    },
+    PNCounter {
+        key: Key,
+        #[serde(deserialize_with = "deserialize_delta")]
+        value: Delta,
+    },
+    HashCounter {
+        key: Key,
+        member: Member,
+        #[serde(deserialize_with = "deserialize_delta")]
+        delta: Delta,
+    },
+    HashSetValue {
+        key: Key,
+        member: Member,
+        value: Value,
+    },
+    HashUnsetKey {
+        key: Key,
+        member: Member,
+    },
+    BlockFinished {
+        #[serde(with = "point_serde")]
+        point: Point,
+        #[serde(default)]
+        direction: BlockDirection,
+    },
+    Rollback {
+        #[serde(with = "point_serde")]
+        point: Point,
+    },
+}
+
+impl From<CRDTCommandJson> for CRDTCommand {
+    fn from(x: CRDTCommandJson) -> Self {
+        match x {
+            CRDTCommandJson::BlockStarting { point, direction } => {
+                CRDTCommand::BlockStarting(point, direction)
+            }
+            CRDTCommandJson::SetAdd { set, member } => CRDTCommand::SetAdd(set, member),
+            CRDTCommandJson::SetRemove { set, member } => CRDTCommand::SetRemove(set, member),
+            CRDTCommandJson::SortedSetAdd { set, member, delta } => {
+                CRDTCommand::SortedSetAdd(set, member, delta)
+            }
+            CRDTCommandJson::SortedSetRemove { set, member, delta } => {
+                CRDTCommand::SortedSetRemove(set, member, delta)
+            }
+            CRDTCommandJson::TwoPhaseSetAdd { set, member } => {
+                CRDTCommand::TwoPhaseSetAdd(set, member)
+            }
+            CRDTCommandJson::TwoPhaseSetRemove { set, member } => {
+                CRDTCommand::TwoPhaseSetRemove(set, member)
+            }
+            CRDTCommandJson::GrowOnlySetAdd { set, member } => {
+                CRDTCommand::GrowOnlySetAdd(set, member)
+            }
+            CRDTCommandJson::LastWriteWins {
+                key,
+                value,
+                timestamp,
+            } => CRDTCommand::LastWriteWins(key, value, timestamp),
+            CRDTCommandJson::AnyWriteWins { key, value } => CRDTCommand::AnyWriteWins(key, value),
+            CRDTCommandJson::PNCounter { key, value } => CRDTCommand::PNCounter(key, value),
+            CRDTCommandJson::HashCounter { key, member, delta } => {
+                CRDTCommand::HashCounter(key, member, delta)
+            }
+            CRDTCommandJson::HashSetValue { key, member, value } => {
+                CRDTCommand::HashSetValue(key, member, value)
+            }
+            CRDTCommandJson::HashUnsetKey { key, member } => CRDTCommand::HashUnsetKey(key, member),
+            CRDTCommandJson::BlockFinished { point, direction } => {
+                CRDTCommand::BlockFinished(point, direction)
+            }
+            CRDTCommandJson::Rollback { point } => CRDTCommand::Rollback(point),
+        }
+    }
+}
+
+impl From<CRDTCommand> for CRDTCommandJson {
+    fn from(x: CRDTCommand) -> Self {
+        match x {
+            CRDTCommand::BlockStarting(point, direction) => {
+                CRDTCommandJson::BlockStarting { point, direction }
+            }
+            CRDTCommand::SetAdd(set, member) => CRDTCommandJson::SetAdd { set, member },
+            CRDTCommand::SetRemove(set, member) => CRDTCommandJson::SetRemove { set, member },
+            CRDTCommand::SortedSetAdd(set, member, delta) => {
+                CRDTCommandJson::SortedSetAdd { set, member, delta }
+            }
+            CRDTCommand::SortedSetRemove(set, member, delta) => {
+                CRDTCommandJson::SortedSetRemove { set, member, delta }
+            }
+            CRDTCommand::TwoPhaseSetAdd(set, member) => {
+                CRDTCommandJson::TwoPhaseSetAdd { set, member }
+            }
+            CRDTCommand::TwoPhaseSetRemove(set, member) => {
+                CRDTCommandJson::TwoPhaseSetRemove { set, member }
+            }
+            CRDTCommand::GrowOnlySetAdd(set, member) => {
+                CRDTCommandJson::GrowOnlySetAdd { set, member }
+            }
+            CRDTCommand::LastWriteWins(key, value, timestamp) => CRDTCommandJson::LastWriteWins {
+                key,
+                value,
+                timestamp,
+            },
+            CRDTCommand::AnyWriteWins(key, value) => CRDTCommandJson::AnyWriteWins { key, value },
+            CRDTCommand::PNCounter(key, value) => CRDTCommandJson::PNCounter { key, value },
+            CRDTCommand::HashCounter(key, member, delta) => {
+                CRDTCommandJson::HashCounter { key, member, delta }
+            }
+            CRDTCommand::HashSetValue(key, member, value) => {
+                CRDTCommandJson::HashSetValue { key, member, value }
+            }
+            CRDTCommand::HashUnsetKey(key, member) => CRDTCommandJson::HashUnsetKey { key, member },
+            CRDTCommand::BlockFinished(point, direction) => {
+                CRDTCommandJson::BlockFinished { point, direction }
+            }
+            CRDTCommand::Rollback(point) => CRDTCommandJson::Rollback { point },
+        }
+    }
+}
+
+/// Accepts deltas either as a JSON number or as a stringified integer
+fn deserialize_delta<'de, D>(deserializer: D) -> Result<Delta, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum IntOrString {
+        Int(Delta),
+        String(String),
+    }
+
+    match IntOrString::deserialize(deserializer)? {
+        IntOrString::Int(x) => Ok(x),
+        IntOrString::String(x) => Delta::from_str(&x).map_err(serde::de::Error::custom),
+    }
+}
+
+/// Points in the same format as chain events, `{"slot":...,"hash":...}` or
+/// `"origin"`
+mod point_serde {
+    use pallas::network::miniprotocols::Point;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum PointJson {
+        Specific { slot: u64, hash: String },
+        Origin(String),
+    }
+
+    pub fn serialize<S: Serializer>(point: &Point, serializer: S) -> Result<S::Ok, S::Error> {
+        super::point_to_json(point.clone()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Point, D::Error> {
+        match PointJson::deserialize(deserializer)? {
+            PointJson::Specific { slot, hash } => {
+                let hash = hex::decode(hash).map_err(serde::de::Error::custom)?;
+                Ok(Point::Specific(slot, hash))
+            }
+            PointJson::Origin(x) if x == "origin" => Ok(Point::Origin),
+            PointJson::Origin(x) => Err(serde::de::Error::custom(format!("invalid point {}", x))),
+        }
+    }
 }
 
 impl CRDTCommand {
-    pub fn block_starting(block: &Block) -> CRDTCommand {
+    pub fn block_starting(block: &Block, direction: BlockDirection) -> CRDTCommand {
         let header = block.header.as_ref().unwrap();
         let point = Point::Specific(header.slot, header.hash.to_vec());
-        CRDTCommand::BlockStarting(point)
+        CRDTCommand::BlockStarting(point, direction)
     }
 
     pub fn set_add(prefix: Option<&str>, key: &str, member: String) -> CRDTCommand {
@@ -374,94 +697,96 @@ impl CRDTCommand {
         CRDTCommand::HashCounter(key, member, delta)
     }
 
-    pub fn block_finished(block: &Block) -> CRDTCommand {
+    pub fn block_finished(block: &Block, direction: BlockDirection) -> CRDTCommand {
         let header = block.header.as_ref().unwrap();
         let point = Point::Specific(header.slot, header.hash.to_vec());
-        CRDTCommand::BlockFinished(point)
+        CRDTCommand::BlockFinished(point, direction)
+    }
+
+    /// Decodes a command as emitted by reducers, e.g.
+    /// `{"command":"SetAdd","set":"...","member":"..."}`
+    pub fn from_json(value: &JsonValue) -> Result<CRDTCommand, serde_json::Error> {
+        CRDTCommand::deserialize(value)
     }
 
-    pub fn from_json(value: &JsonValue) -> Result<CRDTCommand, String> {
+    pub fn to_json(&self) -> JsonValue {
+        json!(self)
+    }
+}
+
+/// Typed parameter bound to a placeholder of a SQL statement
+#[derive(Clone, Debug)]
+pub enum SqlParam {
+    Null,
+    Text(String),
+    BigInt(i64),
+    Bytea(Vec<u8>),
+    Json(JsonValue),
+    Numeric(Decimal),
+}
+
+impl SqlParam {
+    /// Parses a tagged parameter such as `{"type":"bigint","value":"123"}`
+    pub fn from_json(value: &JsonValue) -> Result<SqlParam, String> {
         let obj = value.as_object().ok_or("Expected a JSON object")?;
 
-        match obj.get("command").and_then(JsonValue::as_str) {
-            Some("SetAdd") => {
-                let set = extract_string(obj, "set")?;
-                let member = extract_string(obj, "member")?;
-                Ok(CRDTCommand::SetAdd(set, member))
-            }
-            Some("SetRemove") => {
-                let set = extract_string(obj, "set")?;
-                let member = extract_string(obj, "member")?;
-                Ok(CRDTCommand::SetRemove(set, member))
-            }
-            Some("SortedSetAdd") => {
-                let set = extract_string(obj, "set")?;
-                let member = extract_string(obj, "member")?;
-                let delta = extract_delta(obj, "delta")?;
-                Ok(CRDTCommand::SortedSetAdd(set, member, delta))
-            }
-            Some("SortedSetRemove") => {
-                let set = extract_string(obj, "set")?;
-                let member = extract_string(obj, "member")?;
-                let delta = extract_delta(obj, "delta")?;
-                Ok(CRDTCommand::SortedSetRemove(set, member, delta))
-            }
-            Some("AnyWriteWins") => {
-                let key = extract_string(obj, "key")?;
-                let value = extract_value(obj, "value")?;
-                Ok(CRDTCommand::AnyWriteWins(key, value))
-            }
-            Some("LastWriteWins") => {
-                let key = extract_string(obj, "key")?;
-                let value = extract_value(obj, "value")?;
-                let ts = extract_timestamp(obj, "timestamp")?;
-                Ok(CRDTCommand::LastWriteWins(key, value, ts))
-            }
-            Some("PNCounter") => {
-                let key = extract_string(obj, "key")?;
-                let delta = extract_delta(obj, "value")?;
-                Ok(CRDTCommand::PNCounter(key, delta))
-            }
-            Some("HashCounter") => {
-                let key = extract_string(obj, "key")?;
-                let member = extract_string(obj, "member")?;
-                let delta = extract_delta(obj, "delta")?;
-                Ok(CRDTCommand::HashCounter(key, member, delta))
-            }
-            Some("HashSetValue") => {
-                let key = extract_string(obj, "key")?;
-                let member = extract_string(obj, "member")?;
-                let value = extract_value(obj, "value")?;
-                Ok(CRDTCommand::HashSetValue(key, member, value))
-            }
-            Some("HashUnsetKey") => {
-                let key = extract_string(obj, "key")?;
-                let member = extract_string(obj, "member")?;
-                Ok(CRDTCommand::HashUnsetKey(key, member))
-            }
-            _ => Err("Unknown CRDTCommand".into()),
+        match obj.get("type").and_then(JsonValue::as_str) {
+            Some("null") => Ok(SqlParam::Null),
+            Some("text") => Ok(SqlParam::Text(extract_string(obj, "value")?)),
+            Some("bigint") => Ok(SqlParam::BigInt(extract_delta(obj, "value")?)),
+            Some("bytea") => {
+                let encoded = extract_string(obj, "hex")?;
+                let bytes = hex::decode(encoded).map_err(|_| "Expected valid hex for key hex")?;
+                Ok(SqlParam::Bytea(bytes))
+            }
+            Some("json") => obj
+                .get("value")
+                .cloned()
+                .map(SqlParam::Json)
+                .ok_or_else(|| "Expected a value for key value".into()),
+            Some("numeric") => {
+                let value = match obj.get("value") {
+                    Some(JsonValue::String(s)) => s.clone(),
+                    Some(JsonValue::Number(n)) => n.to_string(),
+                    _ => return Err("Expected a number or stringified number for key value".into()),
+                };
+
+                Decimal::from_str_exact(&value)
+                    .map(SqlParam::Numeric)
+                    .map_err(|_| format!("Failed to parse numeric value {}", value))
+            }
+            _ => Err("Unknown SqlParam type".into()),
         }
     }
 }
 
 #[derive(Clone, Debug)]
 pub enum RDBMSCommand {
-    BlockStarting(Point),
+    BlockStarting(Point, BlockDirection),
     ExecuteSQL(String),
-    BlockFinished(Point),
+    /// a single statement whose positional placeholders (`$1` in Postgres,
+    /// `?1` in SQLite, `?` in MySQL) are bound to the given params
+    ExecuteParameterizedSQL(String, Vec<SqlParam>),
+    /// inserts a row or, if one with the same key columns exists, overwrites
+    /// its values except for the increment columns, which are added to the
+    /// existing ones
+    Upsert(Table, Vec<Column>, Vec<(Column, SqlParam)>, Vec<Column>),
+    /// deletes the rows whose columns equal all of the given values
+    Delete(Table, Vec<(Column, SqlParam)>),
+    BlockFinished(Point, BlockDirection),
 }
 
 impl RDBMSCommand {
-    pub fn block_starting(block: &Block) -> RDBMSCommand {
+    pub fn block_starting(block: &Block, direction: BlockDirection) -> RDBMSCommand {
         let header = block.header.as_ref().unwrap();
         let point = Point::Specific(header.slot, header.hash.to_vec());
-        RDBMSCommand::BlockStarting(point)
+        RDBMSCommand::BlockStarting(point, direction)
     }
 
-    pub fn block_finished(block: &Block) -> RDBMSCommand {
+    pub fn block_finished(block: &Block, direction: BlockDirection) -> RDBMSCommand {
         let header = block.header.as_ref().unwrap();
         let point = Point::Specific(header.slot, header.hash.to_vec());
-        RDBMSCommand::BlockFinished(point)
+        RDBMSCommand::BlockFinished(point, direction)
     }
 
     pub fn from_json(value: &serde_json::Value) -> Result<RDBMSCommand, String> {
@@ -472,6 +797,51 @@ impl RDBMSCommand {
                 let sql = extract_string(obj, "sql")?;
                 Ok(RDBMSCommand::ExecuteSQL(sql))
             }
+            Some("ExecuteParameterizedSQL") => {
+                let sql = extract_string(obj, "sql")?;
+                let params = obj
+                    .get("params")
+                    .and_then(JsonValue::as_array)
+                    .ok_or("Expected an array for key params")?
+                    .iter()
+                    .map(SqlParam::from_json)
+                    .collect::<Result<_, _>>()?;
+                Ok(RDBMSCommand::ExecuteParameterizedSQL(sql, params))
+            }
+            Some("Upsert") => {
+                let table = extract_string(obj, "table")?;
+                let key = extract_string_list(obj, "key")?;
+                let values = extract_columns(obj, "values")?;
+                let increment = match obj.get("increment") {
+                    Some(_) => extract_string_list(obj, "increment")?,
+                    None => vec![],
+                };
+
+                if key.is_empty() {
+                    return Err("Expected at least one key column".into());
+                }
+
+                let missing = key
+                    .iter()
+                    .chain(increment.iter())
+                    .find(|x| !values.iter().any(|(column, _)| column == *x));
+
+                if let Some(column) = missing {
+                    return Err(format!("Expected a value for column {}", column));
+                }
+
+                Ok(RDBMSCommand::Upsert(table, key, values, increment))
+            }
+            Some("Delete") => {
+                let table = extract_string(obj, "table")?;
+                let filter = extract_columns(obj, "where")?;
+
+                if filter.is_empty() {
+                    return Err("Expected at least one column for key where".into());
+                }
+
+                Ok(RDBMSCommand::Delete(table, filter))
+            }
             _ => Err("Unknown RDBMSCommand".into()),
         }
     }
@@ -484,6 +854,32 @@ fn extract_string(obj: &serde_json::Map<String, JsonValue>, key: &str) -> Result
         .ok_or_else(|| format!("Expected a string for key {}", key))
 }
 
+fn extract_string_list(
+    obj: &serde_json::Map<String, JsonValue>,
+    key: &str,
+) -> Result<Vec<String>, String> {
+    obj.get(key)
+        .and_then(JsonValue::as_array)
+        .and_then(|x| {
+            x.iter()
+                .map(|i| i.as_str().map(String::from))
+                .collect::<Option<_>>()
+        })
+        .ok_or_else(|| format!("Expected an array of strings for key {}", key))
+}
+
+fn extract_columns(
+    obj: &serde_json::Map<String, JsonValue>,
+    key: &str,
+) -> Result<Vec<(Column, SqlParam)>, String> {
+    obj.get(key)
+        .and_then(JsonValue::as_object)
+        .ok_or_else(|| format!("Expected an object of columns for key {}", key))?
+        .iter()
+        .map(|(column, value)| Ok((column.clone(), SqlParam::from_json(value)?)))
+        .collect()
+}
+
 fn extract_delta(obj: &serde_json::Map<String, JsonValue>, key: &str) -> Result<i64, String> {
     match obj.get(key) {
         Some(JsonValue::Number(num)) if num.is_i64() => num
@@ -498,20 +894,9 @@ fn extract_delta(obj: &serde_json::Map<String, JsonValue>, key: &str) -> Result<
     }
 }
 
-fn extract_timestamp(obj: &serde_json::Map<String, JsonValue>, key: &str) -> Result<u64, String> {
-    obj.get(key)
-        .and_then(JsonValue::as_u64)
-        .ok_or_else(|| format!("Expected a timestamp for key {}", key))
-}
-
-fn extract_value(obj: &serde_json::Map<String, JsonValue>, key: &str) -> Result<Value, String> {
-    obj.get(key)
-        .cloned()
-        .map(Value::Json)
-        .ok_or_else(|| format!("Expected a value for key {}", key))
-}
-
 pub type SourceOutputPort = gasket::messaging::tokio::OutputPort<ChainEvent>;
+pub type EnrichInputPort = gasket::messaging::tokio::InputPort<ChainEvent>;
+pub type EnrichOutputPort = gasket::messaging::tokio::OutputPort<ChainEvent>;
 pub type ReduceInputPort = gasket::messaging::tokio::InputPort<ChainEvent>;
 pub type ReduceOutputPort = gasket::messaging::tokio::OutputPort<StorageEvent>;
 pub type StorageInputPort = gasket::messaging::tokio::InputPort<StorageEvent>;
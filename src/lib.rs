@@ -1,3 +1,4 @@
+pub mod enrich;
 pub mod framework;
 pub mod reduce;
 pub mod source;
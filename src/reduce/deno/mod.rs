@@ -1,26 +1,43 @@
-use deno_core::error::AnyError;
+use deno_core::error::{generic_error, AnyError};
 use deno_runtime::deno_core;
 use deno_runtime::deno_core::op2;
-use deno_runtime::deno_core::ModuleSpecifier;
 use deno_runtime::deno_core::OpState;
-use deno_runtime::permissions::PermissionsContainer;
+use deno_runtime::deno_core::{
+    FsModuleLoader, ModuleLoader, ModuleSourceFuture, ModuleSpecifier, ResolutionKind,
+};
+use deno_runtime::permissions::{Permissions, PermissionsContainer, PermissionsOptions};
 use deno_runtime::worker::MainWorker as DenoWorker;
 use deno_runtime::worker::WorkerOptions;
 use gasket::framework::*;
+use pallas::network::miniprotocols::Point;
 use serde::Deserialize;
-use std::path::PathBuf;
+use std::cell::RefCell;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
 use std::rc::Rc;
+use std::time::Duration;
 
 use crate::framework::*;
 
+mod state;
+
+use state::{op_state_get, op_state_query, StateClient};
+
+// how long a block sent to storage can take to be committed when the reducer
+// reads state, before the next unit is retried
+const STORAGE_TIMEOUT: Duration = Duration::from_secs(60);
+
 deno_core::extension!(
     deno_filter,
     ops = [
         op_pop_record,
         op_put_record,
+        op_put_error,
         op_read_file,
         op_write_file,
-        op_remove_file
+        op_remove_file,
+        op_state_get,
+        op_state_query
     ]
 );
 
@@ -32,87 +49,384 @@ pub fn op_pop_record(state: &mut OpState) -> Result<serde_json::Value, AnyError>
     Ok(j)
 }
 
+/// Output of a settled reducer call, kept in the op state until collected.
+/// Its absence means the returned promise never settled.
+struct ReduceOutput(Option<serde_json::Value>);
+
 #[op2]
 pub fn op_put_record(
     state: &mut OpState,
     #[serde] value: serde_json::Value,
 ) -> Result<(), AnyError> {
     match value {
-        serde_json::Value::Null => (),
-        _ => state.put(value),
+        serde_json::Value::Null => state.put(ReduceOutput(None)),
+        _ => state.put(ReduceOutput(Some(value))),
     };
 
     Ok(())
 }
 
+/// Reason of a failed reducer call, kept in the op state until collected
+struct ReduceFailure(String);
+
+#[op2(fast)]
+pub fn op_put_error(state: &mut OpState, #[string] message: String) -> Result<(), AnyError> {
+    state.put(ReduceFailure(message));
+    Ok(())
+}
+
+/// Directory the file ops of the reducer are confined to
+struct DataDir(PathBuf);
+
+/// Resolves a path given by the reducer within its data directory, rejecting
+/// absolute paths or any attempt to escape it
+fn resolve_data_path(state: &OpState, path: &str) -> Result<PathBuf, AnyError> {
+    let dir = state
+        .try_borrow::<DataDir>()
+        .ok_or_else(|| generic_error("no data directory configured for the reducer"))?;
+
+    let relative = Path::new(path);
+
+    let escapes = relative
+        .components()
+        .any(|x| !matches!(x, Component::Normal(_) | Component::CurDir));
+
+    if escapes {
+        return Err(generic_error(format!(
+            "path {} is outside of the data directory",
+            path
+        )));
+    }
+
+    Ok(dir.0.join(relative))
+}
+
 #[op2(async)]
 #[string]
-async fn op_read_file(#[string] path: String) -> Result<String, AnyError> {
+async fn op_read_file(
+    state: Rc<RefCell<OpState>>,
+    #[string] path: String,
+) -> Result<String, AnyError> {
+    let path = resolve_data_path(&state.borrow(), &path)?;
     let contents = tokio::fs::read_to_string(path).await?;
     Ok(contents)
 }
 
 #[op2(async)]
-async fn op_write_file(#[string] path: String, #[string] contents: String) -> Result<(), AnyError> {
+async fn op_write_file(
+    state: Rc<RefCell<OpState>>,
+    #[string] path: String,
+    #[string] contents: String,
+) -> Result<(), AnyError> {
+    let path = resolve_data_path(&state.borrow(), &path)?;
     tokio::fs::write(path, contents).await?;
     Ok(())
 }
 
 #[op2(fast)]
-fn op_remove_file(#[string] path: String) -> Result<(), AnyError> {
+fn op_remove_file(state: &mut OpState, #[string] path: String) -> Result<(), AnyError> {
+    let path = resolve_data_path(state, &path)?;
     std::fs::remove_file(path)?;
     Ok(())
 }
 
-async fn setup_deno(main_module: &PathBuf) -> DenoWorker {
-    let empty_module = deno_core::ModuleSpecifier::parse("data:text/javascript;base64,").unwrap();
+// an empty list would grant access to everything
+fn allow_list<T>(items: Vec<T>) -> Option<Vec<T>> {
+    match items.is_empty() {
+        true => None,
+        false => Some(items),
+    }
+}
+
+// the reducer always has access to its own module and data directory
+fn readable_paths(stage: &Stage) -> Vec<PathBuf> {
+    let mut out = stage.permissions.allow_read.clone();
+
+    if let Some(dir) = stage.main_module.parent() {
+        out.push(dir.to_path_buf());
+    }
+
+    if let Some(dir) = &stage.data_dir {
+        out.push(dir.clone());
+    }
+
+    out
+}
+
+fn build_permissions(stage: &Stage) -> Result<Permissions, Error> {
+    let mut allow_write = stage.permissions.allow_write.clone();
+
+    if let Some(dir) = &stage.data_dir {
+        allow_write.push(dir.clone());
+    }
+
+    let options = PermissionsOptions {
+        allow_read: allow_list(readable_paths(stage)),
+        allow_write: allow_list(allow_write),
+        allow_net: allow_list(stage.permissions.allow_net.clone()),
+        allow_env: allow_list(stage.permissions.allow_env.clone()),
+        prompt: false,
+        ..Default::default()
+    };
+
+    Permissions::from_options(&options).map_err(Error::config)
+}
+
+/// Loads modules from the filesystem like `FsModuleLoader`, but only from
+/// within the paths the reducer is allowed to read, since module loading
+/// isn't subject to the runtime permissions
+struct SandboxedModuleLoader {
+    roots: Vec<PathBuf>,
+}
+
+impl SandboxedModuleLoader {
+    fn new(paths: Vec<PathBuf>) -> Self {
+        // paths that don't exist yet can't hold any module
+        let roots = paths.iter().filter_map(|x| x.canonicalize().ok()).collect();
+
+        Self { roots }
+    }
+
+    fn check(&self, specifier: &ModuleSpecifier) -> Result<(), AnyError> {
+        let path = specifier
+            .to_file_path()
+            .map_err(|_| generic_error(format!("module {} is not a local file", specifier)))?;
+
+        // symlinks and `..` segments are resolved so that they can't escape
+        // the allowed paths
+        let path = path.canonicalize()?;
+
+        match self.roots.iter().any(|x| path.starts_with(x)) {
+            true => Ok(()),
+            false => Err(generic_error(format!(
+                "module {} is outside of the paths the reducer can read",
+                specifier
+            ))),
+        }
+    }
+}
+
+impl ModuleLoader for SandboxedModuleLoader {
+    fn resolve(
+        &self,
+        specifier: &str,
+        referrer: &str,
+        kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, AnyError> {
+        FsModuleLoader.resolve(specifier, referrer, kind)
+    }
+
+    fn load(
+        &self,
+        module_specifier: &ModuleSpecifier,
+        maybe_referrer: Option<&ModuleSpecifier>,
+        is_dyn_import: bool,
+    ) -> Pin<Box<ModuleSourceFuture>> {
+        if let Err(err) = self.check(module_specifier) {
+            return Box::pin(futures::future::ready(Err(err)));
+        }
+
+        FsModuleLoader.load(module_specifier, maybe_referrer, is_dyn_import)
+    }
+}
+
+async fn setup_deno(stage: &Stage) -> Result<DenoWorker, Error> {
+    let main_module = &stage.main_module;
+    let config = &stage.config;
+
+    let empty_module =
+        deno_core::ModuleSpecifier::parse("data:text/javascript;base64,").map_err(Error::custom)?;
+
+    if let Some(dir) = &stage.data_dir {
+        std::fs::create_dir_all(dir).map_err(Error::config)?;
+    }
+
+    let permissions = build_permissions(stage)?;
 
     let mut deno = DenoWorker::bootstrap_from_options(
         empty_module,
-        PermissionsContainer::allow_all(),
+        PermissionsContainer::new(permissions),
         WorkerOptions {
-            module_loader: Rc::new(deno_core::FsModuleLoader),
+            module_loader: Rc::new(SandboxedModuleLoader::new(readable_paths(stage))),
             extensions: vec![deno_filter::init_ops()],
             ..Default::default()
         },
     );
 
-    let module_code = deno_core::FastString::from(std::fs::read_to_string(main_module).unwrap());
+    if let Some(dir) = &stage.data_dir {
+        deno.js_runtime
+            .op_state()
+            .borrow_mut()
+            .put(DataDir(dir.clone()));
+    }
+
+    if let Some(backend) = &stage.state {
+        let client = StateClient::connect(backend).await?;
+
+        deno.js_runtime.op_state().borrow_mut().put(Rc::new(client));
+    }
+
+    let module_code = std::fs::read_to_string(main_module).map_err(Error::config)?;
+    let module_code = deno_core::FastString::from(module_code);
+
+    let module_specifier = ModuleSpecifier::from_file_path(main_module)
+        .map_err(|_| Error::config(format!("invalid main module {}", main_module.display())))?;
 
     deno.js_runtime
-        .load_side_module(
-            &ModuleSpecifier::from_file_path(main_module).unwrap(),
-            Some(module_code),
-        )
+        .load_side_module(&module_specifier, Some(module_code))
         .await
-        .unwrap();
+        .map_err(Error::custom)?;
 
+    // the reducer config is passed to the optional init hook once and to
+    // every apply / undo call afterwards
     let runtime_js = format!(
         r#"
-        import("file://{}").then(({{ apply, undo }}) => {{
+        import("file://{}").then(async ({{ apply, undo, init }}) => {{
+            const config = {};
+            const core = Deno[Deno.internal].core;
+
+            // read-only lookups of the state persisted by the storage stage
+            const state = {{
+                get: (key) => core.opAsync("op_state_get", key),
+                query: (sql, params) => core.opAsync("op_state_query", sql, params ?? []),
+            }};
+
+            if (init) {{
+                await init(config);
+            }}
+
             globalThis.scrolls = {{
                 apply: apply,
                 undo: undo,
+                config: config,
+                state: state,
             }}
         }});
         "#,
-        main_module.clone().display().to_string()
+        main_module.clone().display().to_string(),
+        config
     );
 
     let runtime_code = deno_core::FastString::from(runtime_js);
 
-    let res = deno.execute_script("[scrolls:runtime.js]", runtime_code);
-    deno.run_event_loop(false).await.unwrap();
-    res.unwrap();
+    deno.execute_script("[scrolls:runtime.js]", runtime_code)
+        .map_err(Error::custom)?;
 
-    deno
+    deno.run_event_loop(false).await.map_err(Error::custom)?;
+
+    Ok(deno)
+}
+
+fn decode_command(
+    storage_event: &str,
+    index: usize,
+    item: serde_json::Value,
+) -> Result<StorageEvent, CommandError> {
+    // block markers and rollbacks are sent by the stage itself, a reducer
+    // emitting them would break the atomicity of blocks
+    match storage_event {
+        "CRDT" => match CRDTCommand::from_json(&item) {
+            Ok(
+                CRDTCommand::BlockStarting(..)
+                | CRDTCommand::BlockFinished(..)
+                | CRDTCommand::Rollback(..),
+            ) => Err(CommandError::new(
+                index,
+                item,
+                "block markers and rollbacks can't be emitted by reducers",
+            )),
+            Ok(x) => Ok(StorageEvent::CRDT(x)),
+            Err(err) => Err(CommandError::new(index, item, err)),
+        },
+        "RDBMS" => match RDBMSCommand::from_json(&item) {
+            Ok(RDBMSCommand::BlockStarting(..) | RDBMSCommand::BlockFinished(..)) => {
+                Err(CommandError::new(
+                    index,
+                    item,
+                    "block markers and rollbacks can't be emitted by reducers",
+                ))
+            }
+            Ok(x) => Ok(StorageEvent::RDBMS(x)),
+            Err(err) => Err(CommandError::new(index, item, err)),
+        },
+        x => Err(CommandError::new(
+            index,
+            item,
+            format!("unknown storage event {}", x),
+        )),
+    }
+}
+
+/// Markers wrapping the storage events of a block
+fn block_markers(
+    direction: BlockDirection,
+    block: &Block,
+    storage_event: &str,
+) -> Result<(StorageEvent, StorageEvent), Error> {
+    match storage_event {
+        "CRDT" => Ok((
+            StorageEvent::CRDT(CRDTCommand::block_starting(block, direction)),
+            StorageEvent::CRDT(CRDTCommand::block_finished(block, direction)),
+        )),
+        "RDBMS" => Ok((
+            StorageEvent::RDBMS(RDBMSCommand::block_starting(block, direction)),
+            StorageEvent::RDBMS(RDBMSCommand::block_finished(block, direction)),
+        )),
+        x => Err(Error::config(format!("unknown storage event {}", x))),
+    }
+}
+
+/// Latest block sent to the storage stage, which has to be committed before
+/// the reducer can read state consistent with it
+enum InFlight {
+    Apply(Point),
+    Undo(Point),
+    // a rollback of everything after the point
+    Rollback(Point),
+}
+
+impl InFlight {
+    fn is_committed(&self, latest: Option<Point>) -> bool {
+        match self {
+            InFlight::Apply(point) => {
+                matches!(latest, Some(x) if x.slot_or_default() >= point.slot_or_default())
+            }
+            InFlight::Undo(point) => {
+                !matches!(latest, Some(x) if x.slot_or_default() >= point.slot_or_default())
+            }
+            InFlight::Rollback(point) => {
+                !matches!(latest, Some(x) if x.slot_or_default() > point.slot_or_default())
+            }
+        }
+    }
 }
 
 pub struct Worker {
     runtime: DenoWorker,
+    buffer: super::RollbackBuffer,
+    in_flight: Option<InFlight>,
 }
 
 impl Worker {
+    /// Waits for the storage stage to commit the latest block sent to it,
+    /// retrying the current unit if it takes too long
+    async fn wait_for_storage(&mut self, cursor: &Cursor) -> Result<(), WorkerError> {
+        if let Some(in_flight) = &self.in_flight {
+            let committed = cursor
+                .wait_until(STORAGE_TIMEOUT, |latest| in_flight.is_committed(latest))
+                .await;
+
+            if !committed {
+                tracing::warn!("timed out waiting for storage to commit the latest block");
+                return Err(WorkerError::Retry);
+            }
+        }
+
+        self.in_flight = None;
+
+        Ok(())
+    }
+
     async fn reduce(
         &mut self,
         method: &str,
@@ -122,30 +436,169 @@ impl Worker {
 
         deno.js_runtime.op_state().borrow_mut().put(record);
 
+        // reducers may return a promise, which is awaited while the event
+        // loop runs. Rejections are reported back through op_put_error.
         let script = format!(
-            r#"Deno[Deno.internal].core.ops.op_put_record(scrolls.{}(Deno[Deno.internal].core.ops.op_pop_record()));"#,
+            r#"
+            (async () => {{
+                const ops = Deno[Deno.internal].core.ops;
+                try {{
+                    const output = await scrolls.{}(ops.op_pop_record(), scrolls.config);
+                    ops.op_put_record(output);
+                }} catch (err) {{
+                    ops.op_put_error(String(err?.stack ?? err));
+                }}
+            }})();
+            "#,
             method
         );
 
         let script = deno_core::FastString::from(script);
-        let res = deno.execute_script("<anon>", script);
+        deno.execute_script("<anon>", script)
+            .map_err(|err| err.to_string())?;
+
+        deno.run_event_loop(false)
+            .await
+            .map_err(|err| err.to_string())?;
+
+        let failure: Option<ReduceFailure> = deno.js_runtime.op_state().borrow_mut().try_take();
+
+        if let Some(ReduceFailure(message)) = failure {
+            return Err(message);
+        }
 
-        deno.run_event_loop(false).await.unwrap();
+        // the event loop runs out of work while a promise that never settles
+        // is pending, which would otherwise commit the block empty
+        let output: Option<ReduceOutput> = deno.js_runtime.op_state().borrow_mut().try_take();
 
-        res.unwrap();
+        match output {
+            Some(ReduceOutput(x)) => Ok(x),
+            None => Err(String::from("the returned promise never settled")),
+        }
+    }
 
-        let output = deno.js_runtime.op_state().borrow_mut().try_take();
+    /// Runs the reducer over the block and decodes its output into storage
+    /// events
+    async fn reduce_block(
+        &mut self,
+        direction: BlockDirection,
+        point: &Point,
+        record: &Record,
+        storage_event: &str,
+    ) -> Result<Vec<StorageEvent>, Error> {
+        let method = direction.method();
+
+        let reduced = self
+            .reduce(method, record.clone())
+            .await
+            .map_err(|err| Error::reducer(method, point, err))?;
+
+        let items = match reduced {
+            Some(serde_json::Value::Array(items)) => items,
+            Some(x) => vec![x],
+            None => vec![],
+        };
 
-        Ok(output)
+        items
+            .into_iter()
+            .enumerate()
+            .map(|(index, item)| {
+                decode_command(storage_event, index, item).map_err(|err| {
+                    Error::reducer(method, point, format!("{}, got {}", err, err.item))
+                })
+            })
+            .collect()
+    }
+
+    /// Reduces the block and sends its storage events, wrapped in the block
+    /// markers. Returns false if the output of the reducer was skipped, in
+    /// which case the block is still sent empty so that storage tracks it.
+    async fn process_block(
+        &mut self,
+        direction: BlockDirection,
+        point: &Point,
+        record: &Record,
+        stage: &mut Stage,
+    ) -> Result<bool, WorkerError> {
+        let block = match record {
+            Record::ParsedBlock(x) => x,
+            _ => {
+                tracing::error!("expected a parsed block");
+                return Err(WorkerError::Panic);
+            }
+        };
+
+        let (starting, finished) =
+            block_markers(direction, block, &stage.storage_event).or_panic()?;
+
+        // state read by the reducer must reflect every block reduced so far
+        self.wait_for_storage(&stage.cursor).await?;
+
+        // events are only sent once the whole block has been reduced, so that
+        // a failed block can be retried or skipped as a whole. There's
+        // nothing to revert when undoing a block that was skipped.
+        let (events, reduced) =
+            if direction == BlockDirection::Undo && self.buffer.is_skipped(point) {
+                tracing::debug!("skipping undo of skipped block {:?}", point);
+                (vec![], false)
+            } else {
+                match self
+                    .reduce_block(direction, point, record, &stage.storage_event)
+                    .await
+                {
+                    Ok(x) => (x, true),
+                    Err(err) => match stage.on_error {
+                        ErrorMode::Halt => {
+                            tracing::error!("{}", err);
+                            return Err(WorkerError::Retry);
+                        }
+                        ErrorMode::Skip => {
+                            tracing::warn!("skipping block, {}", err);
+                            (vec![], false)
+                        }
+                    },
+                }
+            };
+
+        let events = std::iter::once(starting)
+            .chain(events)
+            .chain(std::iter::once(finished));
+
+        for event in events {
+            stage
+                .output
+                .send(gasket::messaging::Message::from(event))
+                .await
+                .or_panic()?;
+        }
+
+        // storage only tracks specific points, there's nothing to wait for
+        if stage.state.is_some() && matches!(point, Point::Specific(..)) {
+            self.in_flight = match direction {
+                BlockDirection::Apply => Some(InFlight::Apply(point.clone())),
+                BlockDirection::Undo => Some(InFlight::Undo(point.clone())),
+            };
+        }
+
+        stage.ops_count.inc(1);
+
+        Ok(reduced)
     }
 }
 
 #[async_trait::async_trait(?Send)]
 impl gasket::framework::Worker<Stage> for Worker {
     async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
-        let runtime = setup_deno(&stage.main_module).await;
-
-        Ok(Self { runtime })
+        let runtime = setup_deno(stage).await.or_panic()?;
+
+        Ok(Self {
+            runtime,
+            buffer: super::RollbackBuffer::new(
+                stage.rollback_buffer,
+                stage.cursor.latest_known_point(),
+            ),
+            in_flight: None,
+        })
     }
 
     async fn schedule(
@@ -158,90 +611,61 @@ impl gasket::framework::Worker<Stage> for Worker {
     }
 
     async fn execute(&mut self, unit: &ChainEvent, stage: &mut Stage) -> Result<(), WorkerError> {
-        match unit {
-            ChainEvent::Apply(_, record) | ChainEvent::Undo(_, record) => {
-                let block = if let Record::ParsedBlock(block) = record {
-                    block
-                } else {
-                    return Err(WorkerError::Panic);
-                };
-
-                let method = match unit {
-                    ChainEvent::Apply(_, _) => "apply",
-                    ChainEvent::Undo(_, _) => "undo",
-                    _ => unreachable!(),
-                };
+        let unit = &unit
+            .clone()
+            .try_map_record(Record::into_parsed)
+            .or_panic()?;
 
-                let event = match stage.storage_event.as_str() {
-                    "CRDT" => StorageEvent::CRDT(CRDTCommand::block_starting(block)),
-                    "RDBMS" => StorageEvent::RDBMS(RDBMSCommand::block_starting(block)),
-                    _ => return Err(WorkerError::Panic),
+        match unit {
+            ChainEvent::Apply(point, record) => {
+                let reduced = self
+                    .process_block(BlockDirection::Apply, point, record, stage)
+                    .await?;
+
+                match reduced {
+                    true => self.buffer.push(point.clone(), record.clone()),
+                    false => self.buffer.push_skipped(point.clone(), record.clone()),
+                }
+            }
+            ChainEvent::Undo(point, record) => {
+                self.process_block(BlockDirection::Undo, point, record, stage)
+                    .await?;
+                self.buffer.remove(point);
+            }
+            ChainEvent::Reset(point) => {
+                let undos = match stage.storage_event.as_str() {
+                    // blocks that are no longer buffered are reverted by the
+                    // rollback command sent afterwards
+                    "CRDT" => self.buffer.undos_after(point),
+                    _ => self.buffer.rollback_to(point).or_panic()?,
                 };
 
-                stage
-                    .output
-                    .send(gasket::messaging::Message::from(event))
-                    .await
-                    .or_panic()?;
-
-                let reduced = self.reduce(method, record.clone()).await.unwrap();
-
-                if let Some(reduced) = reduced {
-                    match reduced {
-                        serde_json::Value::Array(items) => {
-                            for item in items {
-                                let event = match stage.storage_event.as_str() {
-                                    "CRDT" => {
-                                        StorageEvent::CRDT(CRDTCommand::from_json(&item).unwrap())
-                                    }
-                                    "RDBMS" => {
-                                        StorageEvent::RDBMS(RDBMSCommand::from_json(&item).unwrap())
-                                    }
-                                    _ => return Err(WorkerError::Panic),
-                                };
-
-                                stage
-                                    .output
-                                    .send(gasket::messaging::Message::from(event))
-                                    .await
-                                    .or_panic()?;
-                            }
-                        }
-                        _ => {
-                            let event = match stage.storage_event.as_str() {
-                                "CRDT" => {
-                                    StorageEvent::CRDT(CRDTCommand::from_json(&reduced).unwrap())
-                                }
-                                "RDBMS" => {
-                                    StorageEvent::RDBMS(RDBMSCommand::from_json(&reduced).unwrap())
-                                }
-                                _ => return Err(WorkerError::Panic),
-                            };
-
-                            stage
-                                .output
-                                .send(gasket::messaging::Message::from(event))
-                                .await
-                                .or_panic()?;
-                        }
+                for undo in undos {
+                    if let ChainEvent::Undo(point, record) = undo {
+                        self.process_block(BlockDirection::Undo, &point, &record, stage)
+                            .await?;
+                        self.buffer.remove(&point);
                     }
-
-                    stage.ops_count.inc(1);
                 }
 
-                let event = match stage.storage_event.as_str() {
-                    "CRDT" => StorageEvent::CRDT(CRDTCommand::block_finished(block)),
-                    "RDBMS" => StorageEvent::RDBMS(RDBMSCommand::block_finished(block)),
-                    _ => return Err(WorkerError::Panic),
-                };
-
-                stage
-                    .output
-                    .send(gasket::messaging::Message::from(event))
-                    .await
-                    .or_panic()?;
+                // reverts anything committed after the point that's no
+                // longer buffered, e.g. blocks applied before a restart
+                if stage.storage_event == "CRDT" {
+                    stage
+                        .output
+                        .send(gasket::messaging::Message::from(StorageEvent::CRDT(
+                            CRDTCommand::Rollback(point.clone()),
+                        )))
+                        .await
+                        .or_panic()?;
+
+                    // the rollback may revert blocks that weren't buffered,
+                    // which the next block has to wait for as well
+                    if stage.state.is_some() {
+                        self.in_flight = Some(InFlight::Rollback(point.clone()));
+                    }
+                }
             }
-            _ => panic!("Unhandled ChainEvent variant or Record type in execute"),
         };
 
         Ok(())
@@ -253,30 +677,106 @@ impl gasket::framework::Worker<Stage> for Worker {
 pub struct Stage {
     main_module: PathBuf,
     storage_event: String,
+    rollback_buffer: Option<usize>,
+    config: serde_json::Value,
+    on_error: ErrorMode,
+    permissions: PermissionsConfig,
+    data_dir: Option<PathBuf>,
+    state: Option<StateBackend>,
 
     pub input: ReduceInputPort,
     pub output: ReduceOutputPort,
 
+    cursor: Cursor,
+
     #[metric]
     ops_count: gasket::metrics::Counter,
 }
 
+/// What to do with a block when the reducer fails on it
+#[derive(Deserialize, Default, Clone)]
+pub enum ErrorMode {
+    /// retry the block according to the retry policy, halting the pipeline
+    /// once retries are exhausted
+    #[default]
+    Halt,
+    /// log the error and move on to the next block, ignoring any output of
+    /// the failed one
+    Skip,
+}
+
+/// Resources the reducer is granted access to, anything else is denied
+#[derive(Deserialize, Default, Clone)]
+pub struct PermissionsConfig {
+    #[serde(default)]
+    allow_read: Vec<PathBuf>,
+    #[serde(default)]
+    allow_write: Vec<PathBuf>,
+    /// hosts, optionally with a port (e.g. `example.com:443`)
+    #[serde(default)]
+    allow_net: Vec<String>,
+    /// names of environment variables
+    #[serde(default)]
+    allow_env: Vec<String>,
+}
+
 #[derive(Deserialize)]
 pub struct Config {
     main_module: String,
     storage_event: String,
+    rollback_buffer: Option<usize>,
+    /// arbitrary settings passed to the reducer module
+    config: Option<serde_json::Value>,
+    #[serde(default)]
+    on_error: ErrorMode,
+    #[serde(default)]
+    permissions: PermissionsConfig,
+    /// directory the reducer file ops are confined to, file ops are
+    /// unavailable if not set
+    data_dir: Option<String>,
+    /// exposes read-only lookups of the storage backend to the reducer,
+    /// waiting for each block to be committed before reducing the next one
+    #[serde(default)]
+    state: bool,
 }
 
 impl Config {
-    pub fn bootstrapper(self, _ctx: &Context) -> Result<Stage, Error> {
+    pub fn bootstrapper(self, ctx: &Context) -> Result<Stage, Error> {
+        let permissions = PermissionsConfig {
+            allow_read: resolve_paths(ctx, self.permissions.allow_read),
+            allow_write: resolve_paths(ctx, self.permissions.allow_write),
+            ..self.permissions
+        };
+
+        let state = match (self.state, &ctx.state) {
+            (false, _) => None,
+            (true, Some(backend)) => Some(backend.clone()),
+            (true, None) => {
+                return Err(Error::config(
+                    "state lookups require a Redis or Postgres storage",
+                ))
+            }
+        };
+
         let stage = Stage {
             main_module: PathBuf::from(self.main_module),
             storage_event: self.storage_event,
+            rollback_buffer: self.rollback_buffer,
+            config: self.config.unwrap_or_else(|| serde_json::json!({})),
+            on_error: self.on_error,
+            permissions,
+            data_dir: self.data_dir.map(|x| ctx.current_dir.join(x)),
+            state,
             input: Default::default(),
             output: Default::default(),
+            cursor: ctx.cursor.clone(),
             ops_count: Default::default(),
         };
 
         Ok(stage)
     }
 }
+
+fn resolve_paths(ctx: &Context, paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    paths.into_iter().map(|x| ctx.current_dir.join(x)).collect()
+}
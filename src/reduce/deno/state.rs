@@ -0,0 +1,188 @@
+use deno_core::error::{generic_error, AnyError};
+use deno_runtime::deno_core;
+use deno_runtime::deno_core::op2;
+use deno_runtime::deno_core::OpState;
+use r2d2_redis::redis;
+use r2d2_redis::redis::Commands;
+use serde_json::{json, Value as JsonValue};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use tokio::sync::Mutex;
+use tokio_postgres::types::{ToSql, Type};
+use tokio_postgres::{NoTls, Row};
+
+use crate::framework::*;
+
+/// Connection used by the reducer to read the state persisted by the storage
+/// stage. Only read commands are ever issued through it.
+pub enum StateClient {
+    Redis(RefCell<redis::Connection>),
+    // queries are serialized, each one running in its own read-only
+    // transaction
+    Postgres(Mutex<tokio_postgres::Client>),
+}
+
+impl StateClient {
+    pub async fn connect(backend: &StateBackend) -> Result<Self, Error> {
+        match backend {
+            StateBackend::Redis(url) => {
+                let client = redis::Client::open(url.as_str()).map_err(Error::storage)?;
+                let conn = client.get_connection().map_err(Error::storage)?;
+                Ok(Self::Redis(RefCell::new(conn)))
+            }
+            StateBackend::Postgres(url) => {
+                let (client, connection) = tokio_postgres::connect(url, NoTls)
+                    .await
+                    .map_err(Error::storage)?;
+
+                tokio::spawn(connection);
+
+                Ok(Self::Postgres(Mutex::new(client)))
+            }
+        }
+    }
+
+    fn get(&self, key: &str) -> Result<JsonValue, AnyError> {
+        match self {
+            Self::Redis(conn) => redis_get(&mut conn.borrow_mut(), key),
+            Self::Postgres(_) => Err(generic_error(
+                "state.get is only available with Redis storage, use state.query instead",
+            )),
+        }
+    }
+
+    async fn query(&self, sql: &str, params: &[SqlParam]) -> Result<JsonValue, AnyError> {
+        match self {
+            Self::Postgres(client) => postgres_query(&mut *client.lock().await, sql, params).await,
+            Self::Redis(_) => Err(generic_error(
+                "state.query is only available with Postgres storage, use state.get instead",
+            )),
+        }
+    }
+}
+
+/// Reads a key whatever its type: strings as is, hashes and sorted sets as
+/// objects, sets as arrays and missing keys as null
+fn redis_get(conn: &mut redis::Connection, key: &str) -> Result<JsonValue, AnyError> {
+    let kind: String = redis::cmd("TYPE").arg(key).query(conn)?;
+
+    let value = match kind.as_str() {
+        "none" => JsonValue::Null,
+        "string" => {
+            let value: String = conn.get(key)?;
+            json!(value)
+        }
+        "hash" => {
+            let value: HashMap<String, String> = conn.hgetall(key)?;
+            json!(value)
+        }
+        "set" => {
+            let value: Vec<String> = conn.smembers(key)?;
+            json!(value)
+        }
+        "zset" => {
+            let value: Vec<(String, f64)> = conn.zrange_withscores(key, 0, -1)?;
+            json!(value.into_iter().collect::<HashMap<_, _>>())
+        }
+        "ReJSON-RL" => {
+            let value: String = redis::cmd("JSON.GET").arg(key).query(conn)?;
+            serde_json::from_str(&value)?
+        }
+        x => {
+            return Err(generic_error(format!(
+                "unsupported type {} of key {}",
+                x, key
+            )))
+        }
+    };
+
+    Ok(value)
+}
+
+async fn postgres_query(
+    client: &mut tokio_postgres::Client,
+    sql: &str,
+    params: &[SqlParam],
+) -> Result<JsonValue, AnyError> {
+    let params: Vec<_> = params.iter().map(|x| x as &(dyn ToSql + Sync)).collect();
+
+    let tx = client.build_transaction().read_only(true).start().await?;
+    let rows = tx.query(sql, &params).await?;
+    tx.rollback().await?;
+
+    let rows = rows.iter().map(row_to_json).collect::<Result<_, _>>()?;
+
+    Ok(JsonValue::Array(rows))
+}
+
+/// Converts a row into an object keyed by column name. Values that don't fit
+/// a JS number (bigint, numeric) are returned as strings and bytea as hex.
+fn row_to_json(row: &Row) -> Result<JsonValue, AnyError> {
+    let mut out = serde_json::Map::new();
+
+    for (i, column) in row.columns().iter().enumerate() {
+        let value = match *column.type_() {
+            Type::BOOL => json!(row.try_get::<_, Option<bool>>(i)?),
+            Type::INT2 => json!(row.try_get::<_, Option<i16>>(i)?),
+            Type::INT4 => json!(row.try_get::<_, Option<i32>>(i)?),
+            Type::INT8 => json!(row.try_get::<_, Option<i64>>(i)?.map(|x| x.to_string())),
+            Type::FLOAT4 => json!(row.try_get::<_, Option<f32>>(i)?),
+            Type::FLOAT8 => json!(row.try_get::<_, Option<f64>>(i)?),
+            Type::NUMERIC => json!(row
+                .try_get::<_, Option<rust_decimal::Decimal>>(i)?
+                .map(|x| x.to_string())),
+            Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME => {
+                json!(row.try_get::<_, Option<String>>(i)?)
+            }
+            Type::BYTEA => json!(row.try_get::<_, Option<Vec<u8>>>(i)?.map(hex::encode)),
+            Type::JSON | Type::JSONB => row
+                .try_get::<_, Option<JsonValue>>(i)?
+                .unwrap_or(JsonValue::Null),
+            ref x => {
+                return Err(generic_error(format!(
+                    "unsupported type {} of column {}",
+                    x,
+                    column.name()
+                )))
+            }
+        };
+
+        out.insert(column.name().to_string(), value);
+    }
+
+    Ok(JsonValue::Object(out))
+}
+
+fn client(state: &Rc<RefCell<OpState>>) -> Result<Rc<StateClient>, AnyError> {
+    state
+        .borrow()
+        .try_borrow::<Rc<StateClient>>()
+        .cloned()
+        .ok_or_else(|| generic_error("state lookups are not enabled for the reducer"))
+}
+
+#[op2(async)]
+#[serde]
+pub async fn op_state_get(
+    state: Rc<RefCell<OpState>>,
+    #[string] key: String,
+) -> Result<JsonValue, AnyError> {
+    client(&state)?.get(&key)
+}
+
+#[op2(async)]
+#[serde]
+pub async fn op_state_query(
+    state: Rc<RefCell<OpState>>,
+    #[string] sql: String,
+    #[serde] params: Vec<JsonValue>,
+) -> Result<JsonValue, AnyError> {
+    let params = params
+        .iter()
+        .map(SqlParam::from_json)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(generic_error)?;
+
+    client(&state)?.query(&sql, &params).await
+}
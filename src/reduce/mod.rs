@@ -2,13 +2,105 @@ use gasket::{
     messaging::{RecvPort, SendPort},
     runtime::Tether,
 };
+use pallas::network::miniprotocols::Point;
 use serde::Deserialize;
+use std::collections::{HashSet, VecDeque};
 
 use crate::framework::*;
 
 pub mod deno;
 pub mod rust;
 
+// matches the security parameter (k) of mainnet
+const DEFAULT_ROLLBACK_BUFFER: usize = 2160;
+
+/// Keeps the most recently applied blocks so that a reset to a previous point
+/// can be translated into undo events for every block after that point
+pub struct RollbackBuffer {
+    blocks: VecDeque<(Point, Record)>,
+    max_size: usize,
+    // latest block applied before the buffered ones, either evicted or
+    // committed before the buffer was created, e.g. prior to a restart
+    floor: Option<Point>,
+    // buffered blocks whose reducer output was skipped
+    skipped: HashSet<Point>,
+}
+
+impl RollbackBuffer {
+    /// Creates an empty buffer on top of the latest block already committed
+    pub fn new(max_size: Option<usize>, floor: Option<Point>) -> Self {
+        Self {
+            blocks: VecDeque::new(),
+            max_size: max_size.unwrap_or(DEFAULT_ROLLBACK_BUFFER),
+            floor,
+            skipped: HashSet::new(),
+        }
+    }
+
+    pub fn push(&mut self, point: Point, record: Record) {
+        self.blocks.push_back((point, record));
+
+        if self.blocks.len() > self.max_size {
+            self.floor = self.blocks.pop_front().map(|(point, _)| point);
+
+            if let Some(floor) = &self.floor {
+                self.skipped.remove(floor);
+            }
+        }
+    }
+
+    /// Buffers a block whose reducer output was skipped, so that its undo
+    /// can be skipped as well
+    pub fn push_skipped(&mut self, point: Point, record: Record) {
+        self.skipped.insert(point.clone());
+        self.push(point, record);
+    }
+
+    pub fn is_skipped(&self, point: &Point) -> bool {
+        self.skipped.contains(point)
+    }
+
+    /// Forgets a block that was undone upstream
+    pub fn remove(&mut self, point: &Point) {
+        if matches!(self.blocks.back(), Some((x, _)) if x == point) {
+            self.blocks.pop_back();
+            self.skipped.remove(point);
+        }
+    }
+
+    /// Builds the undo events for every buffered block after the given point,
+    /// newest first. Blocks are only forgotten once each undo is processed.
+    /// Blocks after the point that are no longer buffered are left out.
+    pub fn undos_after(&self, point: &Point) -> Vec<ChainEvent> {
+        let slot = point.slot_or_default();
+
+        self.blocks
+            .iter()
+            .rev()
+            .take_while(|(x, _)| x.slot_or_default() > slot)
+            .map(|(point, record)| ChainEvent::Undo(point.clone(), record.clone()))
+            .collect()
+    }
+
+    /// Same as `undos_after`, failing if any block after the point is no
+    /// longer buffered
+    pub fn rollback_to(&self, point: &Point) -> Result<Vec<ChainEvent>, Error> {
+        let slot = point.slot_or_default();
+
+        if let Some(floor) = &self.floor {
+            if floor.slot_or_default() > slot {
+                return Err(Error::custom(format!(
+                    "rollback to slot {} is deeper than the buffered blocks, which start after slot {}",
+                    slot,
+                    floor.slot_or_default()
+                )));
+            }
+        }
+
+        Ok(self.undos_after(point))
+    }
+}
+
 pub enum Bootstrapper {
     Rust(rust::Stage),
     Deno(deno::Stage),
@@ -1,5 +1,4 @@
 use gasket::framework::*;
-use pallas::network::miniprotocols::Point;
 use serde::Deserialize;
 use utxorpc::proto::cardano::v1::Block;
 
@@ -10,6 +9,65 @@ pub mod balance_by_stake_address;
 
 pub struct Worker {
     reducers: Vec<Reducer>,
+    buffer: super::RollbackBuffer,
+}
+
+impl Worker {
+    async fn apply_block(&mut self, block: &Block, stage: &mut Stage) -> Result<(), WorkerError> {
+        stage
+            .output
+            .send(gasket::messaging::Message::from(StorageEvent::CRDT(
+                CRDTCommand::block_starting(block, BlockDirection::Apply),
+            )))
+            .await
+            .or_panic()?;
+
+        for reducer in self.reducers.iter_mut() {
+            reducer
+                .apply(block.clone(), &mut stage.output)
+                .await
+                .or_panic()?;
+            stage.ops_count.inc(1);
+        }
+
+        stage
+            .output
+            .send(gasket::messaging::Message::from(StorageEvent::CRDT(
+                CRDTCommand::block_finished(block, BlockDirection::Apply),
+            )))
+            .await
+            .or_panic()?;
+
+        Ok(())
+    }
+
+    async fn undo_block(&mut self, block: &Block, stage: &mut Stage) -> Result<(), WorkerError> {
+        stage
+            .output
+            .send(gasket::messaging::Message::from(StorageEvent::CRDT(
+                CRDTCommand::block_starting(block, BlockDirection::Undo),
+            )))
+            .await
+            .or_panic()?;
+
+        for reducer in self.reducers.iter_mut() {
+            reducer
+                .undo(block.clone(), &mut stage.output)
+                .await
+                .or_panic()?;
+            stage.ops_count.inc(1);
+        }
+
+        stage
+            .output
+            .send(gasket::messaging::Message::from(StorageEvent::CRDT(
+                CRDTCommand::block_finished(block, BlockDirection::Undo),
+            )))
+            .await
+            .or_panic()?;
+
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait(?Send)]
@@ -17,6 +75,10 @@ impl gasket::framework::Worker<Stage> for Worker {
     async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
         Ok(Self {
             reducers: stage.reducers.clone(),
+            buffer: super::RollbackBuffer::new(
+                stage.rollback_buffer,
+                stage.cursor.latest_known_point(),
+            ),
         })
     }
 
@@ -30,58 +92,42 @@ impl gasket::framework::Worker<Stage> for Worker {
     }
 
     async fn execute(&mut self, unit: &ChainEvent, stage: &mut Stage) -> Result<(), WorkerError> {
-        match unit {
-            ChainEvent::Apply(Point::Specific(slot, hash), Record::ParsedBlock(block)) => {
-                stage
-                    .output
-                    .send(gasket::messaging::Message::from(StorageEvent::CRDT(
-                        CRDTCommand::block_starting(&block),
-                    )))
-                    .await
-                    .or_panic()?;
-
-                for reducer in self.reducers.iter_mut() {
-                    reducer
-                        .apply(block.clone(), &mut stage.output)
-                        .await
-                        .or_panic()?;
-                    stage.ops_count.inc(1);
-                }
-
-                stage
-                    .output
-                    .send(gasket::messaging::Message::from(StorageEvent::CRDT(
-                        CRDTCommand::block_finished(&block),
-                    )))
-                    .await
-                    .or_panic()?;
+        let unit = unit
+            .clone()
+            .try_map_record(Record::into_parsed)
+            .or_panic()?;
+
+        match &unit {
+            ChainEvent::Apply(point, record @ Record::ParsedBlock(block)) => {
+                self.apply_block(block, stage).await?;
+                self.buffer.push(point.clone(), record.clone());
             }
-            ChainEvent::Undo(Point::Specific(slot, hash), Record::ParsedBlock(block)) => {
-                stage
-                    .output
-                    .send(gasket::messaging::Message::from(StorageEvent::CRDT(
-                        CRDTCommand::block_starting(&block),
-                    )))
-                    .await
-                    .or_panic()?;
-
-                for reducer in self.reducers.iter_mut() {
-                    reducer
-                        .undo(block.clone(), &mut stage.output)
-                        .await
-                        .or_panic()?;
-                    stage.ops_count.inc(1);
+            ChainEvent::Undo(point, Record::ParsedBlock(block)) => {
+                self.undo_block(block, stage).await?;
+                self.buffer.remove(point);
+            }
+            ChainEvent::Reset(point) => {
+                for undo in self.buffer.undos_after(point) {
+                    if let ChainEvent::Undo(point, Record::ParsedBlock(block)) = undo {
+                        self.undo_block(&block, stage).await?;
+                        self.buffer.remove(&point);
+                    }
                 }
 
+                // reverts anything committed after the point that's no
+                // longer buffered, e.g. blocks applied before a restart
                 stage
                     .output
                     .send(gasket::messaging::Message::from(StorageEvent::CRDT(
-                        CRDTCommand::block_finished(&block),
+                        CRDTCommand::Rollback(point.clone()),
                     )))
                     .await
                     .or_panic()?;
             }
-            _ => panic!("Unhandled ChainEvent variant or Record type in execute"),
+            _ => {
+                tracing::error!("expected a parsed block");
+                return Err(WorkerError::Panic);
+            }
         }
 
         Ok(())
@@ -94,6 +140,8 @@ pub struct Stage {
     pub input: ReduceInputPort,
     pub output: ReduceOutputPort,
     reducers: Vec<Reducer>,
+    rollback_buffer: Option<usize>,
+    cursor: Cursor,
     #[metric]
     ops_count: gasket::metrics::Counter,
     #[metric]
@@ -103,6 +151,7 @@ pub struct Stage {
 #[derive(Deserialize)]
 pub struct Config {
     reducers: Vec<ReducerConfig>,
+    rollback_buffer: Option<usize>,
 }
 
 impl Config {
@@ -111,6 +160,8 @@ impl Config {
             input: Default::default(),
             output: Default::default(),
             reducers: self.reducers.into_iter().map(|x| x.plugin()).collect(),
+            rollback_buffer: self.rollback_buffer,
+            cursor: ctx.cursor.clone(),
             ops_count: Default::default(),
             chain_tip: Default::default(),
         };
@@ -0,0 +1,203 @@
+use gasket::framework::*;
+
+use pallas::ledger::traverse::MultiEraBlock;
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, info};
+
+use crate::framework::*;
+
+type Unit = (Point, Vec<u8>);
+
+// how long each schedule waits for storage to commit the last block before
+// going idle
+const COMMIT_WAIT: Duration = Duration::from_secs(5);
+
+fn read_blocks(path: &Path, encoding: &Encoding) -> Result<Vec<Vec<u8>>, Error> {
+    match encoding {
+        Encoding::Raw => {
+            let bytes = std::fs::read(path).map_err(Error::custom)?;
+            Ok(vec![bytes])
+        }
+        Encoding::Hex => {
+            let contents = std::fs::read_to_string(path).map_err(Error::custom)?;
+
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(|line| hex::decode(line).map_err(Error::parse))
+                .collect()
+        }
+    }
+}
+
+fn load_blocks(dir: &Path, encoding: &Encoding) -> Result<Vec<Unit>, Error> {
+    let mut out = vec![];
+
+    for entry in std::fs::read_dir(dir).map_err(Error::config)? {
+        let path = entry.map_err(Error::custom)?.path();
+
+        if !path.is_file() {
+            continue;
+        }
+
+        for cbor in read_blocks(&path, encoding)? {
+            let block = MultiEraBlock::decode(&cbor).map_err(Error::parse)?;
+            let point = Point::Specific(block.slot(), block.hash().to_vec());
+            out.push((point, cbor));
+        }
+    }
+
+    out.sort_by_key(|(point, _)| point.slot_or_default());
+
+    Ok(out)
+}
+
+pub struct Worker {
+    blocks: VecDeque<Unit>,
+    tip: u64,
+    finalized: bool,
+    // latest block sent downstream
+    last: Option<Point>,
+}
+
+#[async_trait::async_trait(?Send)]
+impl gasket::framework::Worker<Stage> for Worker {
+    async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
+        debug!("loading blocks from {}", stage.path.display());
+
+        let mut blocks = load_blocks(&stage.path, &stage.config.encoding).or_panic()?;
+
+        // skip everything up to (and including) the intersection point
+        let start = super::intersect_points(&stage.cursor, &stage.intersect)
+            .and_then(|points| points.into_iter().next());
+
+        if let Some(start) = start {
+            blocks.retain(|(point, _)| point.slot_or_default() > start.slot_or_default());
+        }
+
+        info!("loaded {} blocks from files", blocks.len());
+
+        let tip = blocks
+            .last()
+            .map(|(point, _)| point.slot_or_default())
+            .unwrap_or_default();
+
+        Ok(Self {
+            blocks: blocks.into(),
+            tip,
+            finalized: false,
+            last: None,
+        })
+    }
+
+    async fn schedule(&mut self, stage: &mut Stage) -> Result<WorkSchedule<Unit>, WorkerError> {
+        if !self.finalized {
+            match self.blocks.pop_front() {
+                Some(unit) => return Ok(WorkSchedule::Unit(unit)),
+                None => {
+                    info!("no more blocks to replay");
+                    self.finalized = true;
+                }
+            }
+        }
+
+        // the daemon stops as soon as this stage is done, so it's only done
+        // once storage has committed every block sent downstream
+        let committed = match &self.last {
+            Some(last) => {
+                stage
+                    .cursor
+                    .wait_until(COMMIT_WAIT, |latest| {
+                        matches!(latest, Some(x) if x.slot_or_default() >= last.slot_or_default())
+                    })
+                    .await
+            }
+            None => true,
+        };
+
+        match committed {
+            true => Ok(WorkSchedule::Done),
+            false => {
+                debug!("waiting for storage to commit the last block");
+                Ok(WorkSchedule::Idle)
+            }
+        }
+    }
+
+    async fn execute(&mut self, unit: &Unit, stage: &mut Stage) -> Result<(), WorkerError> {
+        let (point, cbor) = unit;
+
+        let evt = ChainEvent::Apply(point.clone(), Record::CborBlock(cbor.clone()));
+        stage.output.send(evt.into()).await.or_panic()?;
+
+        self.last = Some(point.clone());
+
+        stage.ops_count.inc(1);
+        stage.chain_tip.set(self.tip as i64);
+
+        if should_finalize(&stage.finalize, point) {
+            info!(
+                "finalize condition reached at slot {}",
+                point.slot_or_default()
+            );
+            self.finalized = true;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Stage)]
+#[stage(name = "source-files", unit = "Unit", worker = "Worker")]
+pub struct Stage {
+    config: Config,
+    path: PathBuf,
+    cursor: Cursor,
+    intersect: IntersectConfig,
+    finalize: Option<FinalizeConfig>,
+    pub output: SourceOutputPort,
+    #[metric]
+    ops_count: gasket::metrics::Counter,
+    #[metric]
+    chain_tip: gasket::metrics::Gauge,
+}
+
+/// How blocks are stored in each file of the directory
+#[derive(Deserialize, Default)]
+pub enum Encoding {
+    /// a single block per file as raw CBOR bytes
+    Raw,
+    /// one hex-encoded CBOR block per line, which covers both a single block
+    /// per file and newline-delimited archives
+    #[default]
+    Hex,
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    path: PathBuf,
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+impl Config {
+    pub fn bootstrapper(self, ctx: &Context) -> Result<Stage, Error> {
+        let stage = Stage {
+            path: ctx.current_dir.join(&self.path),
+            config: self,
+            cursor: ctx.cursor.clone(),
+            intersect: ctx.intersect.clone(),
+            finalize: ctx.finalize.clone(),
+            output: Default::default(),
+            ops_count: Default::default(),
+            chain_tip: Default::default(),
+        };
+
+        Ok(stage)
+    }
+}
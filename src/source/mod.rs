@@ -1,11 +1,69 @@
+use gasket::framework::*;
 use gasket::{messaging::SendPort, runtime::Tether};
+use pallas::codec::Fragment;
+use pallas::network::miniprotocols::{chainsync, Point};
 use serde::Deserialize;
+use tracing::{error, info};
 
 use crate::framework::*;
 
+pub mod files;
+pub mod n2c;
+pub mod n2n;
 pub mod utxorpc;
 
+/// Resolves the points a source should intersect with. Breadcrumbs from a
+/// previous run take precedence over the configured intersect so that we
+/// resume right where we left off. Returns `None` when the source should
+/// intersect at the tip or the origin instead.
+pub fn intersect_points(cursor: &Cursor, intersect: &IntersectConfig) -> Option<Vec<Point>> {
+    if !cursor.is_empty() {
+        return Some(cursor.clone_state().into_iter().collect());
+    }
+
+    intersect.points()
+}
+
+/// Intersects the chainsync client with the chain according to the cursor
+/// and the configured intersect, failing if none of the points are found.
+pub async fn intersect<O>(
+    chainsync: &mut chainsync::Client<O>,
+    cursor: &Cursor,
+    intersect: &IntersectConfig,
+) -> Result<Point, WorkerError>
+where
+    chainsync::Message<O>: Fragment,
+{
+    let point = match intersect_points(cursor, intersect) {
+        Some(points) => {
+            let (point, _) = chainsync
+                .find_intersect(points.clone())
+                .await
+                .or_restart()?;
+
+            match point {
+                Some(x) => x,
+                None => {
+                    error!(?points, "couldn't find an intersection with the chain");
+                    return Err(WorkerError::Panic);
+                }
+            }
+        }
+        None => match intersect {
+            IntersectConfig::Origin => chainsync.intersect_origin().await.or_restart()?,
+            _ => chainsync.intersect_tip().await.or_restart()?,
+        },
+    };
+
+    info!(?point, "intersected chain");
+
+    Ok(point)
+}
+
 pub enum Bootstrapper {
+    Files(files::Stage),
+    N2C(n2c::Stage),
+    N2N(n2n::Stage),
     UtxoRPC(utxorpc::Stage),
 }
 
@@ -16,12 +74,18 @@ impl StageBootstrapper<ChainEvent, ChainEvent> for Bootstrapper {
 
     fn connect_output(&mut self, adapter: OutputAdapter<ChainEvent>) {
         match self {
+            Bootstrapper::Files(p) => p.output.connect(adapter),
+            Bootstrapper::N2C(p) => p.output.connect(adapter),
+            Bootstrapper::N2N(p) => p.output.connect(adapter),
             Bootstrapper::UtxoRPC(p) => p.output.connect(adapter),
         }
     }
 
     fn spawn(self, policy: gasket::runtime::Policy) -> Tether {
         match self {
+            Bootstrapper::Files(x) => gasket::runtime::spawn_stage(x, policy),
+            Bootstrapper::N2C(x) => gasket::runtime::spawn_stage(x, policy),
+            Bootstrapper::N2N(x) => gasket::runtime::spawn_stage(x, policy),
             Bootstrapper::UtxoRPC(x) => gasket::runtime::spawn_stage(x, policy),
         }
     }
@@ -30,12 +94,18 @@ impl StageBootstrapper<ChainEvent, ChainEvent> for Bootstrapper {
 #[derive(Deserialize)]
 #[serde(tag = "type")]
 pub enum Config {
+    Files(files::Config),
+    N2C(n2c::Config),
+    N2N(n2n::Config),
     UtxoRPC(utxorpc::Config),
 }
 
 impl Config {
     pub fn bootstrapper(self, ctx: &Context) -> Result<Bootstrapper, Error> {
         match self {
+            Config::Files(c) => Ok(Bootstrapper::Files(c.bootstrapper(ctx)?)),
+            Config::N2C(c) => Ok(Bootstrapper::N2C(c.bootstrapper(ctx)?)),
+            Config::N2N(c) => Ok(Bootstrapper::N2N(c.bootstrapper(ctx)?)),
             Config::UtxoRPC(c) => Ok(Bootstrapper::UtxoRPC(c.bootstrapper(ctx)?)),
         }
     }
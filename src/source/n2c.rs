@@ -0,0 +1,132 @@
+use gasket::framework::*;
+
+use pallas::ledger::traverse::MultiEraBlock;
+use pallas::network::facades::NodeClient;
+use pallas::network::miniprotocols::chainsync::{BlockContent, NextResponse};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+use std::path::PathBuf;
+use tracing::{debug, info};
+
+use crate::framework::*;
+
+pub struct Worker {
+    client: NodeClient,
+}
+
+impl Worker {
+    async fn process_next(
+        &mut self,
+        stage: &mut Stage,
+        next: &NextResponse<BlockContent>,
+    ) -> Result<(), WorkerError> {
+        match next {
+            NextResponse::RollForward(cbor, tip) => {
+                let block = MultiEraBlock::decode(&cbor.0).or_panic()?;
+                let point = Point::Specific(block.slot(), block.hash().to_vec());
+
+                debug!(slot = block.slot(), "chain sync roll forward");
+
+                let evt = ChainEvent::Apply(point, Record::CborBlock(cbor.0.clone()));
+                stage.output.send(evt.into()).await.or_panic()?;
+
+                stage.ops_count.inc(1);
+                stage.chain_tip.set(tip.0.slot_or_default() as i64);
+            }
+            NextResponse::RollBackward(point, tip) => {
+                debug!(?point, "chain sync roll backward");
+
+                stage
+                    .output
+                    .send(ChainEvent::reset(point.clone()))
+                    .await
+                    .or_panic()?;
+
+                stage.chain_tip.set(tip.0.slot_or_default() as i64);
+            }
+            NextResponse::Await => {
+                info!("chain-sync reached the tip of the chain");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl gasket::framework::Worker<Stage> for Worker {
+    async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
+        debug!("connecting");
+
+        let client = NodeClient::connect(&stage.config.socket_path, stage.chain.magic)
+            .await
+            .or_retry()?;
+
+        let mut worker = Self { client };
+
+        super::intersect(worker.client.chainsync(), &stage.cursor, &stage.intersect).await?;
+
+        Ok(worker)
+    }
+
+    async fn schedule(
+        &mut self,
+        _: &mut Stage,
+    ) -> Result<WorkSchedule<NextResponse<BlockContent>>, WorkerError> {
+        let client = self.client.chainsync();
+
+        let next = match client.has_agency() {
+            true => client.request_next().await.or_restart()?,
+            false => client.recv_while_must_reply().await.or_restart()?,
+        };
+
+        Ok(WorkSchedule::Unit(next))
+    }
+
+    async fn execute(
+        &mut self,
+        unit: &NextResponse<BlockContent>,
+        stage: &mut Stage,
+    ) -> Result<(), WorkerError> {
+        self.process_next(stage, unit).await
+    }
+}
+
+#[derive(Stage)]
+#[stage(
+    name = "source-n2c",
+    unit = "NextResponse<BlockContent>",
+    worker = "Worker"
+)]
+pub struct Stage {
+    config: Config,
+    chain: GenesisValues,
+    cursor: Cursor,
+    intersect: IntersectConfig,
+    pub output: SourceOutputPort,
+    #[metric]
+    ops_count: gasket::metrics::Counter,
+    #[metric]
+    chain_tip: gasket::metrics::Gauge,
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    socket_path: PathBuf,
+}
+
+impl Config {
+    pub fn bootstrapper(self, ctx: &Context) -> Result<Stage, Error> {
+        let stage = Stage {
+            config: self,
+            chain: ctx.chain.clone().into(),
+            cursor: ctx.cursor.clone(),
+            intersect: ctx.intersect.clone(),
+            output: Default::default(),
+            ops_count: Default::default(),
+            chain_tip: Default::default(),
+        };
+
+        Ok(stage)
+    }
+}
@@ -0,0 +1,253 @@
+use gasket::framework::*;
+
+use pallas::ledger::traverse::MultiEraHeader;
+use pallas::network::facades::PeerClient;
+use pallas::network::miniprotocols::chainsync::{HeaderContent, NextResponse};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+use tracing::{debug, error, info, warn};
+
+use crate::framework::*;
+
+fn to_traverse(header: &HeaderContent) -> Result<MultiEraHeader<'_>, WorkerError> {
+    let out = match header.byron_prefix {
+        Some((subtag, _)) => MultiEraHeader::decode(header.variant, Some(subtag), &header.cbor),
+        None => MultiEraHeader::decode(header.variant, None, &header.cbor),
+    };
+
+    out.or_panic()
+}
+
+pub struct Worker {
+    peer_session: PeerClient,
+}
+
+impl Worker {
+    async fn connect(peer_address: &str, stage: &Stage) -> Result<Self, WorkerError> {
+        debug!(peer_address, "connecting");
+
+        let peer_session = PeerClient::connect(peer_address, stage.chain.magic)
+            .await
+            .or_retry()?;
+
+        let mut worker = Self { peer_session };
+
+        let chainsync = worker.peer_session.chainsync();
+
+        if let Err(err) = super::intersect(chainsync, &stage.cursor, &stage.intersect).await {
+            worker.peer_session.abort();
+            return Err(err);
+        }
+
+        Ok(worker)
+    }
+
+    async fn process_next(
+        &mut self,
+        stage: &mut Stage,
+        next: &NextResponse<HeaderContent>,
+    ) -> Result<(), WorkerError> {
+        match next {
+            NextResponse::RollForward(header, tip) => {
+                let header = to_traverse(header)?;
+                let point = Point::Specific(header.slot(), header.hash().to_vec());
+
+                debug!(slot = header.slot(), "chain sync roll forward");
+
+                let block = self
+                    .peer_session
+                    .blockfetch()
+                    .fetch_single(point.clone())
+                    .await
+                    .or_restart()?;
+
+                let evt = ChainEvent::Apply(point, Record::CborBlock(block));
+                stage.output.send(evt.into()).await.or_panic()?;
+
+                stage.ops_count.inc(1);
+                stage.chain_tip.set(tip.0.slot_or_default() as i64);
+            }
+            NextResponse::RollBackward(point, tip) => {
+                debug!(?point, "chain sync roll backward");
+
+                stage
+                    .output
+                    .send(ChainEvent::reset(point.clone()))
+                    .await
+                    .or_panic()?;
+
+                stage.chain_tip.set(tip.0.slot_or_default() as i64);
+            }
+            NextResponse::Await => {
+                info!("chain-sync reached the tip of the chain");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl gasket::framework::Worker<Stage> for Worker {
+    async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
+        // peers are tried in turn until one of them connects and intersects
+        for peer_address in stage.config.peers.iter() {
+            match Self::connect(peer_address, stage).await {
+                Ok(worker) => return Ok(worker),
+                Err(err) => warn!(%peer_address, ?err, "failed to connect to peer"),
+            }
+        }
+
+        error!("couldn't connect to any of the peers");
+
+        Err(WorkerError::Retry)
+    }
+
+    async fn schedule(
+        &mut self,
+        _: &mut Stage,
+    ) -> Result<WorkSchedule<NextResponse<HeaderContent>>, WorkerError> {
+        let client = self.peer_session.chainsync();
+
+        let next = match client.has_agency() {
+            true => client.request_next().await.or_restart()?,
+            false => client.recv_while_must_reply().await.or_restart()?,
+        };
+
+        Ok(WorkSchedule::Unit(next))
+    }
+
+    async fn execute(
+        &mut self,
+        unit: &NextResponse<HeaderContent>,
+        stage: &mut Stage,
+    ) -> Result<(), WorkerError> {
+        self.process_next(stage, unit).await
+    }
+}
+
+#[derive(Stage)]
+#[stage(
+    name = "source-n2n",
+    unit = "NextResponse<HeaderContent>",
+    worker = "Worker"
+)]
+pub struct Stage {
+    config: Config,
+    chain: GenesisValues,
+    cursor: Cursor,
+    intersect: IntersectConfig,
+    pub output: SourceOutputPort,
+    #[metric]
+    ops_count: gasket::metrics::Counter,
+    #[metric]
+    chain_tip: gasket::metrics::Gauge,
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    /// addresses of the relays to sync from, tried in order until one of
+    /// them connects
+    peers: Vec<String>,
+}
+
+impl Config {
+    pub fn bootstrapper(self, ctx: &Context) -> Result<Stage, Error> {
+        if self.peers.is_empty() {
+            return Err(Error::config("at least one peer is required"));
+        }
+
+        let stage = Stage {
+            config: self,
+            chain: ctx.chain.clone().into(),
+            cursor: ctx.cursor.clone(),
+            intersect: ctx.intersect.clone(),
+            output: Default::default(),
+            ops_count: Default::default(),
+            chain_tip: Default::default(),
+        };
+
+        Ok(stage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pallas::network::facades::PeerServer;
+    use pallas::network::miniprotocols::chainsync::{ClientRequest, Tip};
+    use tokio::net::TcpListener;
+
+    /// Serves a single session that intersects at the first point offered by
+    /// the client and then rolls back to it. Returns the offered points.
+    async fn mock_peer(listener: TcpListener, magic: u64) -> Vec<Point> {
+        let mut server = PeerServer::accept(&listener, magic).await.unwrap();
+        let chainsync = server.chainsync();
+
+        let points = match chainsync.recv_while_idle().await.unwrap() {
+            Some(ClientRequest::Intersect(points)) => points,
+            _ => panic!("expected an intersect request"),
+        };
+
+        let point = points[0].clone();
+        let tip = Tip(point.clone(), 1);
+
+        chainsync
+            .send_intersect_found(point.clone(), tip.clone())
+            .await
+            .unwrap();
+
+        match chainsync.recv_while_idle().await.unwrap() {
+            Some(ClientRequest::RequestNext) => (),
+            _ => panic!("expected a request for the next block"),
+        }
+
+        chainsync.send_roll_backward(point, tip).await.unwrap();
+
+        points
+    }
+
+    async fn unused_address() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_next_peer() {
+        let chain = GenesisValues::preview();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(mock_peer(listener, chain.magic));
+
+        let point = Point::Specific(100, vec![1; 32]);
+
+        let mut stage = Stage {
+            config: Config {
+                peers: vec![unused_address().await, address],
+            },
+            chain,
+            cursor: Cursor::new([point.clone()].into()),
+            intersect: IntersectConfig::Tip,
+            output: Default::default(),
+            ops_count: Default::default(),
+            chain_tip: Default::default(),
+        };
+
+        let mut worker = <Worker as gasket::framework::Worker<Stage>>::bootstrap(&stage)
+            .await
+            .unwrap();
+
+        let next = <Worker as gasket::framework::Worker<Stage>>::schedule(&mut worker, &mut stage)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            next,
+            WorkSchedule::Unit(NextResponse::RollBackward(x, _)) if x == point
+        ));
+
+        // breadcrumbs take precedence over the configured intersect
+        assert_eq!(server.await.unwrap(), vec![point]);
+    }
+}
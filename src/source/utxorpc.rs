@@ -11,15 +11,33 @@ use tracing::{debug, error, info};
 use utxorpc::proto::sync::v1::any_chain_block::Chain;
 use utxorpc::proto::sync::v1::chain_sync_service_client::ChainSyncServiceClient;
 use utxorpc::proto::sync::v1::follow_tip_response::Action;
-use utxorpc::proto::sync::v1::{BlockRef, DumpHistoryRequest, FollowTipRequest, FollowTipResponse};
+use utxorpc::proto::sync::v1::{
+    AnyChainBlock, BlockRef, DumpHistoryRequest, FollowTipRequest, FollowTipResponse,
+};
 
 use crate::framework::*;
 use serde_json::json;
 
+fn block_point(block: &AnyChainBlock) -> Option<Point> {
+    match block.chain.as_ref()? {
+        Chain::Cardano(block) => {
+            let header = block.header.as_ref()?;
+            Some(Point::Specific(header.slot, header.hash.to_vec()))
+        }
+        Chain::Raw(bytes) => {
+            let block = MultiEraBlock::decode(bytes).ok()?;
+            Some(Point::Specific(block.slot(), block.hash().to_vec()))
+        }
+    }
+}
+
 pub struct Worker {
     client: ChainSyncServiceClient<Channel>,
     stream: Option<Streaming<FollowTipResponse>>,
     block_ref: Option<BlockRef>,
+    // the start of the history dump is inclusive, so when resuming from the
+    // cursor its first block is the latest one already processed
+    processed: Option<Point>,
     max_items_per_page: u32,
 }
 
@@ -151,8 +169,16 @@ impl Worker {
 
         self.block_ref = result.next_token;
 
-        if !result.block.is_empty() {
-            let actions: Vec<Action> = result.block.into_iter().map(Action::Apply).collect();
+        let mut blocks = result.block;
+
+        if let Some(processed) = self.processed.take() {
+            if blocks.first().and_then(block_point) == Some(processed) {
+                blocks.remove(0);
+            }
+        }
+
+        if !blocks.is_empty() {
+            let actions: Vec<Action> = blocks.into_iter().map(Action::Apply).collect();
             return Ok(WorkSchedule::Unit(actions));
         }
 
@@ -169,22 +195,18 @@ impl gasket::framework::Worker<Stage> for Worker {
             .await
             .or_panic()?;
 
-        let mut point: Option<(u64, Vec<u8>)> = match stage.intersect.clone() {
-            IntersectConfig::Point(slot, hash) => Some((slot, hash.into())),
+        let point = super::intersect_points(&stage.cursor, &stage.intersect)
+            .and_then(|points| points.into_iter().next());
+
+        let block_ref = match point {
+            Some(Point::Specific(slot, hash)) => Some(BlockRef {
+                index: slot,
+                hash: hash.into(),
+            }),
             _ => None,
         };
 
-        if let Some(latest_point) = stage.cursor.latest_known_point() {
-            point = match latest_point {
-                Point::Specific(slot, hash) => Some((slot, hash)),
-                _ => None,
-            };
-        }
-
-        let block_ref = point.map(|(slot, hash)| BlockRef {
-            index: slot,
-            hash: hash.into(),
-        });
+        let processed = stage.cursor.latest_known_point();
 
         let max_items_per_page = stage.config.max_items_per_page.unwrap_or(20);
 
@@ -193,6 +215,7 @@ impl gasket::framework::Worker<Stage> for Worker {
             stream: None,
             max_items_per_page,
             block_ref,
+            processed,
         })
     }
 
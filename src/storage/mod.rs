@@ -1,14 +1,79 @@
+use gasket::framework::*;
+use gasket::metrics::{Counter, Gauge};
 use gasket::{messaging::RecvPort, runtime::Tether};
+use pallas::network::miniprotocols::Point;
 use serde::Deserialize;
+use std::future::Future;
 
 use crate::framework::*;
 
+mod mysql;
 mod postgres;
 mod redis;
+mod sql;
+mod sqlite;
+
+/// A storage worker that buffers the commands of a block and commits them
+/// atomically along with the cursor once the block is finished
+#[async_trait::async_trait(?Send)]
+trait BlockStore {
+    /// Discards the commands buffered for the current block
+    fn clear_pending(&mut self);
+
+    /// Commits the buffered commands along with the cursor tracking the block
+    async fn commit_block(
+        &mut self,
+        point: &Point,
+        direction: BlockDirection,
+        cursor: &Cursor,
+    ) -> Result<(), WorkerError>;
+}
+
+/// Commits a finished block and tracks it in the cursor of the stage. Blocks
+/// already committed on a previous run are skipped, since they're replayed
+/// from the intersection after a restart.
+async fn finish_block(
+    store: &mut impl BlockStore,
+    point: &Point,
+    direction: BlockDirection,
+    cursor: &Cursor,
+    ops_count: &Counter,
+    latest_block: &Gauge,
+) -> Result<(), WorkerError> {
+    if let Point::Specific(slot, _hash) = point {
+        if direction == BlockDirection::Apply && cursor.is_latest(point) {
+            tracing::debug!(slot, "skipping block that was already committed");
+            store.clear_pending();
+            return Ok(());
+        }
+
+        store.commit_block(point, direction, cursor).await?;
+
+        cursor.track_block(point.clone(), direction);
+
+        ops_count.inc(1);
+        latest_block.set(*slot as i64);
+    }
+
+    Ok(())
+}
+
+/// Runs a future of an async backend to completion before the pipeline
+/// runtime is up, e.g. to load the cursor
+fn block_on<T>(future: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(Error::storage)?;
+
+    runtime.block_on(future)
+}
 
 pub enum Bootstrapper {
     Redis(redis::Stage),
     Postgres(postgres::Stage),
+    Sqlite(sqlite::Stage),
+    Mysql(mysql::Stage),
 }
 
 impl StageBootstrapper<StorageEvent, StorageEvent> for Bootstrapper {
@@ -16,6 +81,8 @@ impl StageBootstrapper<StorageEvent, StorageEvent> for Bootstrapper {
         match self {
             Bootstrapper::Redis(p) => p.input.connect(adapter),
             Bootstrapper::Postgres(p) => p.input.connect(adapter),
+            Bootstrapper::Sqlite(p) => p.input.connect(adapter),
+            Bootstrapper::Mysql(p) => p.input.connect(adapter),
         }
     }
 
@@ -27,6 +94,8 @@ impl StageBootstrapper<StorageEvent, StorageEvent> for Bootstrapper {
         match self {
             Bootstrapper::Redis(x) => gasket::runtime::spawn_stage(x, policy),
             Bootstrapper::Postgres(x) => gasket::runtime::spawn_stage(x, policy),
+            Bootstrapper::Sqlite(x) => gasket::runtime::spawn_stage(x, policy),
+            Bootstrapper::Mysql(x) => gasket::runtime::spawn_stage(x, policy),
         }
     }
 }
@@ -36,13 +105,37 @@ impl StageBootstrapper<StorageEvent, StorageEvent> for Bootstrapper {
 pub enum Config {
     Redis(redis::Config),
     Postgres(postgres::Config),
+    Sqlite(sqlite::Config),
+    Mysql(mysql::Config),
 }
 
 impl Config {
+    /// Loads the breadcrumbs persisted by the storage stage on previous runs
+    pub fn load_cursor(&self) -> Result<Cursor, Error> {
+        match self {
+            Config::Redis(c) => c.load_cursor(),
+            Config::Postgres(c) => c.load_cursor(),
+            Config::Sqlite(c) => c.load_cursor(),
+            Config::Mysql(c) => c.load_cursor(),
+        }
+    }
+
+    /// Backend that reducers can read the persisted state from, if supported
+    pub fn state_backend(&self) -> Option<StateBackend> {
+        match self {
+            Config::Redis(c) => Some(StateBackend::Redis(c.url.clone())),
+            Config::Postgres(c) => Some(StateBackend::Postgres(c.url.clone())),
+            Config::Sqlite(_) => None,
+            Config::Mysql(_) => None,
+        }
+    }
+
     pub fn bootstrapper(self, ctx: &Context) -> Result<Bootstrapper, Error> {
         match self {
             Config::Redis(c) => Ok(Bootstrapper::Redis(c.bootstrapper(ctx)?)),
             Config::Postgres(c) => Ok(Bootstrapper::Postgres(c.bootstrapper(ctx)?)),
+            Config::Sqlite(c) => Ok(Bootstrapper::Sqlite(c.bootstrapper(ctx)?)),
+            Config::Mysql(c) => Ok(Bootstrapper::Mysql(c.bootstrapper(ctx)?)),
         }
     }
 }
@@ -0,0 +1,191 @@
+use gasket::framework::*;
+use mysql_async::prelude::*;
+use mysql_async::{Conn, Pool, TxOpts};
+use pallas::network::miniprotocols::Point;
+use serde::Deserialize;
+
+use super::sql::{Dialect, Statement, CURSOR_SELECT, CURSOR_TABLE_DDL};
+use crate::framework::*;
+
+const DIALECT: Dialect = Dialect::Mysql;
+
+fn to_value(param: &SqlParam) -> mysql_async::Value {
+    match param {
+        SqlParam::Null => mysql_async::Value::NULL,
+        SqlParam::Text(x) => x.clone().into(),
+        SqlParam::BigInt(x) => (*x).into(),
+        SqlParam::Bytea(x) => x.clone().into(),
+        // sent as text, the server converts them to the column type
+        SqlParam::Json(x) => x.to_string().into(),
+        SqlParam::Numeric(x) => x.to_string().into(),
+    }
+}
+
+fn bind<'a>(params: impl Iterator<Item = &'a SqlParam>) -> Vec<mysql_async::Value> {
+    params.map(to_value).collect()
+}
+
+pub struct Worker {
+    pool: Pool,
+    pending: Vec<RDBMSCommand>,
+}
+
+#[async_trait::async_trait(?Send)]
+impl super::BlockStore for Worker {
+    fn clear_pending(&mut self) {
+        self.pending.clear();
+    }
+
+    async fn commit_block(
+        &mut self,
+        point: &Point,
+        direction: BlockDirection,
+        cursor: &Cursor,
+    ) -> Result<(), WorkerError> {
+        // failures are retried rather than restarting the worker, so that the
+        // same block is committed again from the buffered commands. The
+        // transaction is rolled back when dropped without committing.
+        let mut conn = self.pool.get_conn().await.or_retry()?;
+
+        let mut tx = conn.start_transaction(TxOpts::default()).await.or_retry()?;
+
+        for statement in self.pending.iter().filter_map(|x| DIALECT.statement(x)) {
+            match statement {
+                Statement::Batch(sql) => {
+                    tx.query_drop(sql).await.or_retry()?;
+                }
+                Statement::Prepared(sql, params) => {
+                    tx.exec_drop(&*sql, bind(params.into_iter()))
+                        .await
+                        .or_retry()?;
+                }
+            }
+        }
+
+        let cursor = cursor.fork();
+        cursor.track_block(point.clone(), direction);
+        tx.exec_drop(DIALECT.cursor_upsert(), (cursor.to_json(),))
+            .await
+            .or_retry()?;
+
+        tx.commit().await.or_retry()?;
+
+        self.pending.clear();
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl gasket::framework::Worker<Stage> for Worker {
+    async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
+        let pool = Pool::from_url(stage.url.as_str()).or_panic()?;
+
+        let mut conn = pool.get_conn().await.or_restart()?;
+        conn.query_drop(CURSOR_TABLE_DDL).await.or_restart()?;
+        drop(conn);
+
+        Ok(Self {
+            pool,
+            pending: vec![],
+        })
+    }
+
+    async fn schedule(
+        &mut self,
+        stage: &mut Stage,
+    ) -> Result<WorkSchedule<StorageEvent>, WorkerError> {
+        let msg = stage.input.recv().await.or_panic()?;
+        Ok(WorkSchedule::Unit(msg.payload))
+    }
+
+    async fn execute(
+        &mut self,
+        event: &StorageEvent,
+        stage: &mut Stage,
+    ) -> Result<(), WorkerError> {
+        match event {
+            StorageEvent::RDBMS(rdbms_command) => {
+                match rdbms_command {
+                    RDBMSCommand::BlockStarting(..) => {
+                        self.pending.clear();
+                    }
+                    RDBMSCommand::BlockFinished(point, direction) => {
+                        super::finish_block(
+                            self,
+                            point,
+                            *direction,
+                            &stage.cursor,
+                            &stage.ops_count,
+                            &stage.latest_block,
+                        )
+                        .await?;
+                    }
+                    x => self.pending.push(x.clone()),
+                };
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Stage)]
+#[stage(name = "storage-mysql", unit = "StorageEvent", worker = "Worker")]
+pub struct Stage {
+    url: String,
+
+    pub input: StorageInputPort,
+
+    cursor: Cursor,
+
+    #[metric]
+    ops_count: gasket::metrics::Counter,
+
+    #[metric]
+    latest_block: gasket::metrics::Gauge,
+}
+
+#[derive(Default, Debug, Deserialize)]
+pub struct Config {
+    pub url: String,
+}
+
+impl Config {
+    pub fn load_cursor(&self) -> Result<Cursor, Error> {
+        super::block_on(async {
+            let mut conn = Conn::from_url(self.url.as_str())
+                .await
+                .map_err(Error::storage)?;
+
+            conn.query_drop(CURSOR_TABLE_DDL)
+                .await
+                .map_err(Error::storage)?;
+
+            let breadcrumbs: Option<String> = conn
+                .query_first(CURSOR_SELECT)
+                .await
+                .map_err(Error::storage)?;
+
+            conn.disconnect().await.map_err(Error::storage)?;
+
+            match breadcrumbs {
+                Some(json) => Cursor::from_json(&json),
+                None => Ok(Cursor::new(Default::default())),
+            }
+        })
+    }
+
+    pub fn bootstrapper(self, ctx: &Context) -> Result<Stage, Error> {
+        let stage = Stage {
+            input: Default::default(),
+            cursor: ctx.cursor.clone(),
+            url: self.url,
+            ops_count: Default::default(),
+            latest_block: Default::default(),
+        };
+
+        Ok(stage)
+    }
+}
@@ -1,15 +1,154 @@
 use bb8::Pool;
 use bb8_postgres::PostgresConnectionManager;
+use bytes::BytesMut;
 use gasket::framework::*;
 use pallas::network::miniprotocols::Point;
+use tokio_postgres::types::{to_sql_checked, IsNull, ToSql, Type};
 use tokio_postgres::NoTls;
 
 use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use tracing::info;
 
+use super::sql::{Dialect, Statement, CURSOR_SELECT, CURSOR_TABLE_DDL};
 use crate::framework::*;
 
+const DIALECT: Dialect = Dialect::Postgres;
+
+const MIGRATIONS_TABLE_DDL: &str = "CREATE TABLE IF NOT EXISTS scrolls_migrations \
+    (version BIGINT PRIMARY KEY, name TEXT NOT NULL, applied_at TIMESTAMPTZ NOT NULL DEFAULT now())";
+
+const MIGRATION_INSERT: &str = "INSERT INTO scrolls_migrations (version, name) VALUES ($1, $2)";
+
+/// A schema migration read from a `<version>_<name>.sql` file
+struct Migration {
+    version: i64,
+    name: String,
+    sql: String,
+}
+
+/// Reads the migrations of the directory sorted by version
+fn load_migrations(dir: &Path) -> Result<Vec<Migration>, Error> {
+    let mut out = vec![];
+
+    for entry in std::fs::read_dir(dir).map_err(Error::config)? {
+        let path = entry.map_err(Error::config)?.path();
+
+        if !path.is_file() || path.extension().map_or(true, |x| x != "sql") {
+            continue;
+        }
+
+        let stem = path
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .ok_or_else(|| Error::config(format!("invalid migration {}", path.display())))?;
+
+        let (version, name) = stem.split_once('_').unwrap_or((stem, ""));
+
+        let version = version.parse().map_err(|_| {
+            Error::config(format!(
+                "migration {} is not prefixed by a version number",
+                path.display()
+            ))
+        })?;
+
+        let sql = std::fs::read_to_string(&path).map_err(Error::config)?;
+
+        out.push(Migration {
+            version,
+            name: name.to_string(),
+            sql,
+        });
+    }
+
+    out.sort_by_key(|x| x.version);
+
+    if let Some(pair) = out.windows(2).find(|x| x[0].version == x[1].version) {
+        return Err(Error::config(format!(
+            "duplicated migration version {}",
+            pair[0].version
+        )));
+    }
+
+    Ok(out)
+}
+
+impl ToSql for SqlParam {
+    fn to_sql(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        match self {
+            SqlParam::Null => Ok(IsNull::Yes),
+            SqlParam::Text(x) => x.to_sql_checked(ty, out),
+            SqlParam::BigInt(x) => x.to_sql_checked(ty, out),
+            SqlParam::Bytea(x) => x.to_sql_checked(ty, out),
+            SqlParam::Json(x) => x.to_sql_checked(ty, out),
+            SqlParam::Numeric(x) => x.to_sql_checked(ty, out),
+        }
+    }
+
+    // the actual type is checked by each variant when serializing
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+fn bind<'a>(params: impl Iterator<Item = &'a SqlParam>) -> Vec<&'a (dyn ToSql + Sync)> {
+    params.map(|x| x as &(dyn ToSql + Sync)).collect()
+}
+
 pub struct Worker {
     pool: Pool<PostgresConnectionManager<NoTls>>,
+    pending: Vec<RDBMSCommand>,
+}
+
+#[async_trait::async_trait(?Send)]
+impl super::BlockStore for Worker {
+    fn clear_pending(&mut self) {
+        self.pending.clear();
+    }
+
+    async fn commit_block(
+        &mut self,
+        point: &Point,
+        direction: BlockDirection,
+        cursor: &Cursor,
+    ) -> Result<(), WorkerError> {
+        // failures are retried rather than restarting the worker, so that the
+        // same block is committed again from the buffered commands. The
+        // transaction is rolled back when dropped without committing.
+        let mut conn = self.pool.get().await.or_retry()?;
+
+        let tx = conn.transaction().await.or_retry()?;
+
+        for statement in self.pending.iter().filter_map(|x| DIALECT.statement(x)) {
+            match statement {
+                Statement::Batch(sql) => {
+                    tx.batch_execute(sql).await.or_retry()?;
+                }
+                Statement::Prepared(sql, params) => {
+                    let params = bind(params.into_iter());
+                    tx.execute(&*sql, &params).await.or_retry()?;
+                }
+            }
+        }
+
+        let cursor = cursor.fork();
+        cursor.track_block(point.clone(), direction);
+        tx.execute(DIALECT.cursor_upsert().as_str(), &[&cursor.to_json()])
+            .await
+            .or_retry()?;
+
+        tx.commit().await.or_retry()?;
+
+        self.pending.clear();
+
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait(?Send)]
@@ -18,7 +157,51 @@ impl gasket::framework::Worker<Stage> for Worker {
         let manager =
             PostgresConnectionManager::new_from_stringlike(stage.url.clone(), NoTls).or_panic()?;
         let pool = Pool::builder().build(manager).await.or_panic()?;
-        Ok(Self { pool })
+
+        let mut conn = pool.get().await.or_restart()?;
+        conn.execute(CURSOR_TABLE_DDL, &[]).await.or_restart()?;
+
+        if let Some(dir) = &stage.migrations {
+            let migrations = load_migrations(dir).or_panic()?;
+
+            conn.execute(MIGRATIONS_TABLE_DDL, &[]).await.or_restart()?;
+
+            let applied: Vec<i64> = conn
+                .query("SELECT version FROM scrolls_migrations", &[])
+                .await
+                .or_restart()?
+                .iter()
+                .map(|row| row.get(0))
+                .collect();
+
+            for migration in migrations {
+                if applied.contains(&migration.version) {
+                    continue;
+                }
+
+                info!(
+                    version = migration.version,
+                    name = %migration.name,
+                    "applying migration"
+                );
+
+                // each migration is recorded along with its changes so that
+                // a failed one is retried as a whole
+                let tx = conn.transaction().await.or_restart()?;
+                tx.batch_execute(&migration.sql).await.or_panic()?;
+                tx.execute(MIGRATION_INSERT, &[&migration.version, &migration.name])
+                    .await
+                    .or_restart()?;
+                tx.commit().await.or_restart()?;
+            }
+        }
+
+        drop(conn);
+
+        Ok(Self {
+            pool,
+            pending: vec![],
+        })
     }
 
     async fn schedule(
@@ -34,25 +217,24 @@ impl gasket::framework::Worker<Stage> for Worker {
         event: &StorageEvent,
         stage: &mut Stage,
     ) -> Result<(), WorkerError> {
-        let conn = self.pool.get().await.or_restart()?;
-
         match event {
             StorageEvent::RDBMS(rdbms_command) => {
                 match rdbms_command {
-                    RDBMSCommand::BlockStarting(_) => {
-                        conn.execute("BEGIN", &[]).await.or_restart()?;
-                    }
-                    RDBMSCommand::ExecuteSQL(sql) => {
-                        conn.execute(sql, &[]).await.or_restart()?;
+                    RDBMSCommand::BlockStarting(..) => {
+                        self.pending.clear();
                     }
-                    RDBMSCommand::BlockFinished(point) => {
-                        if let Point::Specific(slot, _hash) = point {
-                            conn.execute("COMMIT", &[]).await.or_restart()?;
-
-                            stage.ops_count.inc(1);
-                            stage.latest_block.set(*slot as i64);
-                        }
+                    RDBMSCommand::BlockFinished(point, direction) => {
+                        super::finish_block(
+                            self,
+                            point,
+                            *direction,
+                            &stage.cursor,
+                            &stage.ops_count,
+                            &stage.latest_block,
+                        )
+                        .await?;
                     }
+                    x => self.pending.push(x.clone()),
                 };
             }
             _ => {}
@@ -66,6 +248,7 @@ impl gasket::framework::Worker<Stage> for Worker {
 #[stage(name = "storage-postgres", unit = "StorageEvent", worker = "Worker")]
 pub struct Stage {
     url: String,
+    migrations: Option<PathBuf>,
 
     pub input: StorageInputPort,
 
@@ -81,14 +264,43 @@ pub struct Stage {
 #[derive(Default, Debug, Deserialize)]
 pub struct Config {
     pub url: String,
+    /// directory of `<version>_<name>.sql` files applied at bootstrap, in
+    /// order of version, unless already recorded as applied
+    pub migrations: Option<String>,
 }
 
 impl Config {
+    pub fn load_cursor(&self) -> Result<Cursor, Error> {
+        super::block_on(async {
+            let (client, connection) = tokio_postgres::connect(&self.url, NoTls)
+                .await
+                .map_err(Error::storage)?;
+
+            tokio::spawn(connection);
+
+            client
+                .execute(CURSOR_TABLE_DDL, &[])
+                .await
+                .map_err(Error::storage)?;
+
+            let row = client
+                .query_opt(CURSOR_SELECT, &[])
+                .await
+                .map_err(Error::storage)?;
+
+            match row {
+                Some(row) => Cursor::from_json(row.get(0)),
+                None => Ok(Cursor::new(Default::default())),
+            }
+        })
+    }
+
     pub fn bootstrapper(self, ctx: &Context) -> Result<Stage, Error> {
         let stage = Stage {
             input: Default::default(),
             cursor: ctx.cursor.clone(),
             url: self.url,
+            migrations: self.migrations.map(|x| ctx.current_dir.join(x)),
             ops_count: Default::default(),
             latest_block: Default::default(),
         };
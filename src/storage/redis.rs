@@ -8,6 +8,7 @@ use r2d2_redis::redis::RedisWrite;
 use r2d2_redis::redis::ToRedisArgs;
 use r2d2_redis::RedisConnectionManager;
 use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
 use std::ops::DerefMut;
 use tracing;
 
@@ -22,15 +23,398 @@ impl<'a> ToRedisArgs for &'a Value {
             Value::String(ref x) => x.write_redis_args(out),
             Value::BigInt(ref x) => x.to_string().write_redis_args(out),
             Value::Cbor(ref x) => x.write_redis_args(out),
-            Value::Json(ref x) => todo!("{}", x),
+            Value::Json(ref x) => x.to_string().write_redis_args(out),
         }
     }
 }
 
+/// Adds a member to the materialized set of a 2-phase set unless it's present
+/// in the tombstone set, since removed members can't be added back
+const TWO_PHASE_SET_ADD: &str = r#"
+if redis.call('SISMEMBER', KEYS[2], ARGV[1]) == 0 then
+    return redis.call('SADD', KEYS[1], ARGV[1])
+end
+return 0
+"#;
+
+/// 2-phase sets are kept as the materialized set of live members under the
+/// key itself, so that membership can be queried directly, plus the set of
+/// removed members under this key
+fn tombstones_key(key: &str) -> String {
+    format!("{}.ts", key)
+}
+
+/// Keys mutated by a command, used to journal their previous values
+fn touched_keys(command: &CRDTCommand) -> Vec<String> {
+    match command {
+        CRDTCommand::SetAdd(key, _)
+        | CRDTCommand::SetRemove(key, _)
+        | CRDTCommand::SortedSetAdd(key, _, _)
+        | CRDTCommand::SortedSetRemove(key, _, _)
+        | CRDTCommand::TwoPhaseSetAdd(key, _)
+        | CRDTCommand::GrowOnlySetAdd(key, _)
+        | CRDTCommand::LastWriteWins(key, _, _)
+        | CRDTCommand::AnyWriteWins(key, _)
+        | CRDTCommand::PNCounter(key, _)
+        | CRDTCommand::HashCounter(key, _, _)
+        | CRDTCommand::HashSetValue(key, _, _)
+        | CRDTCommand::HashUnsetKey(key, _) => vec![key.clone()],
+        CRDTCommand::TwoPhaseSetRemove(key, _) => vec![key.clone(), tombstones_key(key)],
+        CRDTCommand::BlockStarting(..)
+        | CRDTCommand::BlockFinished(..)
+        | CRDTCommand::Rollback(..) => vec![],
+    }
+}
+
+/// Queues the equivalent redis command into the pipeline
+fn queue_command(pipe: &mut redis::Pipeline, command: &CRDTCommand, encoding: &JsonEncoding) {
+    match command {
+        CRDTCommand::GrowOnlySetAdd(key, value) => {
+            pipe.sadd(key, value).ignore();
+        }
+        CRDTCommand::TwoPhaseSetAdd(key, value) => {
+            tracing::debug!("adding to 2-phase set [{}], value [{}]", key, value);
+
+            pipe.cmd("EVAL")
+                .arg(TWO_PHASE_SET_ADD)
+                .arg(2)
+                .arg(key)
+                .arg(tombstones_key(key))
+                .arg(value)
+                .ignore();
+        }
+        CRDTCommand::TwoPhaseSetRemove(key, value) => {
+            tracing::debug!("removing from 2-phase set [{}], value [{}]", key, value);
+
+            pipe.sadd(tombstones_key(key), value).ignore();
+            pipe.srem(key, value).ignore();
+        }
+        CRDTCommand::SetAdd(key, value) => {
+            tracing::debug!("adding to set [{}], value [{}]", key, value);
+
+            pipe.sadd(key, value).ignore();
+        }
+        CRDTCommand::SetRemove(key, value) => {
+            tracing::debug!("removing from set [{}], value [{}]", key, value);
+
+            pipe.srem(key, value).ignore();
+        }
+        CRDTCommand::LastWriteWins(key, value, ts) => {
+            tracing::debug!("last write for [{}], slot [{}]", key, ts);
+
+            pipe.zadd(key, value, *ts).ignore();
+        }
+        CRDTCommand::SortedSetAdd(key, value, delta) => {
+            tracing::debug!(
+                "sorted set add [{}], value [{}], delta [{}]",
+                key,
+                value,
+                delta
+            );
+
+            pipe.zincr(key, value, *delta).ignore();
+        }
+        CRDTCommand::SortedSetRemove(key, value, delta) => {
+            tracing::debug!(
+                "sorted set remove [{}], value [{}], delta [{}]",
+                key,
+                value,
+                delta
+            );
+
+            pipe.zincr(key, value, *delta).ignore();
+
+            // removal of dangling scores  (aka garbage collection)
+            pipe.zrembyscore(key, 0, 0).ignore();
+        }
+        CRDTCommand::AnyWriteWins(key, value) => {
+            tracing::debug!("overwrite [{}]", key);
+
+            match (value, encoding) {
+                (Value::Json(x), JsonEncoding::RedisJson) => {
+                    pipe.cmd("JSON.SET")
+                        .arg(key)
+                        .arg("$")
+                        .arg(x.to_string())
+                        .ignore();
+                }
+                _ => {
+                    pipe.set(key, value).ignore();
+                }
+            }
+        }
+        CRDTCommand::PNCounter(key, value) => {
+            tracing::debug!("increasing counter [{}], by [{}]", key, value);
+
+            pipe.incr(key, *value).ignore();
+        }
+        CRDTCommand::HashSetValue(key, member, value) => {
+            tracing::debug!("setting hash key {} member {}", key, member);
+
+            pipe.hset(key, member, value).ignore();
+        }
+        CRDTCommand::HashCounter(key, member, delta) => {
+            tracing::debug!("increasing hash key {} member {} by {}", key, member, delta);
+
+            pipe.hincr(key, member, *delta).ignore();
+        }
+        CRDTCommand::HashUnsetKey(key, member) => {
+            tracing::debug!("deleting hash key {} member {}", key, member);
+
+            pipe.hdel(key, member).ignore();
+        }
+        CRDTCommand::BlockStarting(..)
+        | CRDTCommand::BlockFinished(..)
+        | CRDTCommand::Rollback(..) => {}
+    }
+}
+
+/// Records, for every block, the value each key had before the block mutated
+/// it (as serialized by `DUMP`) so that any command can be reverted, even
+/// non-invertible ones. Entries are stored as a hash per block and indexed by
+/// slot in a sorted set.
+struct Journal {
+    index: String,
+    security_param: usize,
+}
+
+impl Journal {
+    fn member(point: &Point) -> String {
+        match point {
+            Point::Specific(slot, hash) => format!("{}.{}", slot, hex::encode(hash)),
+            Point::Origin => String::from("origin"),
+        }
+    }
+
+    fn entry_key(&self, member: &str) -> String {
+        format!("{}.{}", self.index, member)
+    }
+
+    fn contains(&self, conn: &mut redis::Connection, point: &Point) -> Result<bool, WorkerError> {
+        let key = self.entry_key(&Self::member(point));
+        conn.exists(key).or_retry()
+    }
+
+    /// Queues into the pipeline the journal entry of the block, holding the
+    /// value of every touched key previous to the block, and prunes entries
+    /// that are past the security parameter. Keys that didn't exist are
+    /// recorded with an empty value.
+    fn commit(
+        &self,
+        conn: &mut redis::Connection,
+        pipe: &mut redis::Pipeline,
+        commands: &[CRDTCommand],
+        point: &Point,
+    ) -> Result<(), WorkerError> {
+        let member = Self::member(point);
+        let entry = self.entry_key(&member);
+
+        let mut seen = HashSet::new();
+
+        let keys: Vec<_> = commands
+            .iter()
+            .flat_map(touched_keys)
+            .filter(|x| seen.insert(x.clone()))
+            .collect();
+
+        // reads happen in a single round trip before the pipeline is sent, so
+        // they observe the state previous to the block. The stale entries are
+        // read last.
+        let mut reads = redis::pipe();
+
+        for key in keys.iter() {
+            reads.cmd("DUMP").arg(key);
+        }
+
+        reads.zrange(&self.index, 0, -(self.security_param as isize));
+
+        let mut values: Vec<redis::Value> = reads.query(conn).or_retry()?;
+
+        let stale: Vec<String> = match values.pop() {
+            Some(x) => redis::from_redis_value(&x).or_retry()?,
+            None => vec![],
+        };
+
+        for (key, dump) in keys.into_iter().zip(values) {
+            let dump: Option<Vec<u8>> = redis::from_redis_value(&dump).or_retry()?;
+
+            pipe.hset(&entry, key, dump.unwrap_or_default()).ignore();
+        }
+
+        pipe.zadd(&self.index, &member, point.slot_or_default())
+            .ignore();
+
+        for member in stale {
+            pipe.del(self.entry_key(&member)).ignore();
+            pipe.zrem(&self.index, member).ignore();
+        }
+
+        Ok(())
+    }
+
+    /// Queues into the pipeline the restoration of the state previous to the
+    /// given block, reverting it and any journaled block after it
+    fn undo(
+        &self,
+        conn: &mut redis::Connection,
+        pipe: &mut redis::Pipeline,
+        point: &Point,
+    ) -> Result<(), WorkerError> {
+        self.revert(conn, pipe, point.slot_or_default().to_string())
+    }
+
+    /// Queues into the pipeline the restoration of the state at the given
+    /// point, reverting every journaled block after it
+    fn rollback_to(
+        &self,
+        conn: &mut redis::Connection,
+        pipe: &mut redis::Pipeline,
+        point: &Point,
+    ) -> Result<(), WorkerError> {
+        self.revert(conn, pipe, format!("({}", point.slot_or_default()))
+    }
+
+    /// Reverts the journaled blocks from the given min score onwards, newest
+    /// first
+    fn revert(
+        &self,
+        conn: &mut redis::Connection,
+        pipe: &mut redis::Pipeline,
+        min: String,
+    ) -> Result<(), WorkerError> {
+        let members: Vec<String> = conn.zrangebyscore(&self.index, min, "+inf").or_retry()?;
+
+        if members.is_empty() {
+            return Ok(());
+        }
+
+        // the entries of every reverted block are read in a single round trip
+        let mut reads = redis::pipe();
+
+        for member in members.iter() {
+            reads.hgetall(self.entry_key(member));
+        }
+
+        let entries: Vec<HashMap<String, Vec<u8>>> = reads.query(conn).or_retry()?;
+
+        for (member, values) in members.into_iter().zip(entries).rev() {
+            let entry = self.entry_key(&member);
+
+            for (key, dump) in values {
+                if dump.is_empty() {
+                    pipe.del(key).ignore();
+                } else {
+                    pipe.cmd("RESTORE")
+                        .arg(key)
+                        .arg(0)
+                        .arg(dump)
+                        .arg("REPLACE")
+                        .ignore();
+                }
+            }
+
+            pipe.del(entry).ignore();
+            pipe.zrem(&self.index, member).ignore();
+        }
+
+        Ok(())
+    }
+}
+
 pub struct Worker {
     pool: Pool<RedisConnectionManager>,
     stream: String,
     maxlen: Option<usize>,
+    cursor_key: String,
+    journal: Journal,
+    json_encoding: JsonEncoding,
+    // commands of the block being processed, flushed as a single atomic
+    // pipeline once the block is finished
+    pending: Vec<CRDTCommand>,
+}
+
+#[async_trait::async_trait(?Send)]
+impl super::BlockStore for Worker {
+    fn clear_pending(&mut self) {
+        self.pending.clear();
+    }
+
+    async fn commit_block(
+        &mut self,
+        point: &Point,
+        direction: BlockDirection,
+        cursor: &Cursor,
+    ) -> Result<(), WorkerError> {
+        // failures are retried rather than restarting the worker, so that the
+        // pipeline is rebuilt and sent again from the buffered commands
+        let mut conn = self.pool.get().or_retry()?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+
+        match direction {
+            BlockDirection::Apply => {
+                self.journal
+                    .commit(conn.deref_mut(), &mut pipe, &self.pending, point)?;
+
+                for command in self.pending.iter() {
+                    queue_command(&mut pipe, command, &self.json_encoding);
+                }
+            }
+            // the journal is used to restore the state and the commands of
+            // the undone block are ignored
+            BlockDirection::Undo if self.journal.contains(conn.deref_mut(), point)? => {
+                tracing::debug!("restoring journaled state previous to {:?}", point);
+                self.journal.undo(conn.deref_mut(), &mut pipe, point)?;
+            }
+            // blocks past the security param (or applied before the journal
+            // existed) are reverted through the undo commands of the reducers
+            BlockDirection::Undo => {
+                tracing::warn!(
+                    "no journal entry for undone block {:?}, applying the undo commands",
+                    point
+                );
+
+                for command in self.pending.iter() {
+                    queue_command(&mut pipe, command, &self.json_encoding);
+                }
+            }
+        }
+
+        // persist the breadcrumbs as part of the same transaction
+        let cursor = cursor.fork();
+        cursor.track_block(point.clone(), direction);
+        pipe.set(&self.cursor_key, cursor.to_json()).ignore();
+
+        pipe.query::<()>(conn.deref_mut()).or_retry()?;
+
+        self.pending.clear();
+
+        Ok(())
+    }
+}
+
+impl Worker {
+    /// Reverts every journaled block after the point, used on chain resets
+    /// that go past the blocks known to the reducers, e.g. after a restart
+    fn rollback(&mut self, point: &Point, cursor: &Cursor) -> Result<(), WorkerError> {
+        let mut conn = self.pool.get().or_retry()?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+
+        tracing::debug!("restoring journaled state at {:?}", point);
+        self.journal
+            .rollback_to(conn.deref_mut(), &mut pipe, point)?;
+
+        let cursor = cursor.fork();
+        cursor.rollback_to(point);
+        pipe.set(&self.cursor_key, cursor.to_json()).ignore();
+
+        pipe.query::<()>(conn.deref_mut()).or_retry()?;
+
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait(?Send)]
@@ -47,10 +431,25 @@ impl gasket::framework::Worker<Stage> for Worker {
 
         let maxlen = stage.config.stream_max_length;
 
+        let cursor_key = stage.config.cursor_key();
+
+        let journal = Journal {
+            index: stage
+                .config
+                .journal_name
+                .clone()
+                .unwrap_or(String::from("scrolls-journal")),
+            security_param: stage.config.security_param.unwrap_or(2160),
+        };
+
         Ok(Self {
             pool,
             stream,
             maxlen,
+            cursor_key,
+            journal,
+            json_encoding: stage.config.json_encoding.clone(),
+            pending: vec![],
         })
     }
 
@@ -67,105 +466,29 @@ impl gasket::framework::Worker<Stage> for Worker {
         event: &StorageEvent,
         stage: &mut Stage,
     ) -> Result<(), WorkerError> {
-        let mut conn = self.pool.get().or_restart()?;
-
         match event {
             StorageEvent::CRDT(crdt_command) => {
                 match crdt_command {
-                    CRDTCommand::BlockStarting(_) => {
-                        // start redis transaction
-                        redis::cmd("MULTI").query(conn.deref_mut()).or_restart()?;
-                    }
-                    CRDTCommand::GrowOnlySetAdd(key, value) => {
-                        conn.sadd(key, value).or_restart()?;
-                    }
-                    CRDTCommand::TwoPhaseSetAdd(key, value) => {
-                        tracing::debug!("adding to 2-phase set [{}], value [{}]", key, value);
-
-                        conn.sadd(key, value).or_restart()?;
-                    }
-                    CRDTCommand::TwoPhaseSetRemove(key, value) => {
-                        tracing::debug!("removing from 2-phase set [{}], value [{}]", key, value);
-
-                        conn.sadd(format!("{}.ts", key), value).or_restart()?;
+                    CRDTCommand::BlockStarting(..) => {
+                        self.pending.clear();
                     }
-                    CRDTCommand::SetAdd(key, value) => {
-                        tracing::debug!("adding to set [{}], value [{}]", key, value);
-
-                        conn.sadd(key, value).or_restart()?;
+                    CRDTCommand::BlockFinished(point, direction) => {
+                        super::finish_block(
+                            self,
+                            point,
+                            *direction,
+                            &stage.cursor,
+                            &stage.ops_count,
+                            &stage.latest_block,
+                        )
+                        .await?;
                     }
-                    CRDTCommand::SetRemove(key, value) => {
-                        tracing::debug!("removing from set [{}], value [{}]", key, value);
+                    CRDTCommand::Rollback(point) => {
+                        self.rollback(point, &stage.cursor)?;
 
-                        conn.srem(key, value).or_restart()?;
-                    }
-                    CRDTCommand::LastWriteWins(key, value, ts) => {
-                        tracing::debug!("last write for [{}], slot [{}]", key, ts);
-
-                        conn.zadd(key, value, *ts).or_restart()?;
-                    }
-                    CRDTCommand::SortedSetAdd(key, value, delta) => {
-                        tracing::debug!(
-                            "sorted set add [{}], value [{}], delta [{}]",
-                            key,
-                            value,
-                            delta
-                        );
-
-                        conn.zincr(key, value, *delta).or_restart()?;
-                    }
-                    CRDTCommand::SortedSetRemove(key, value, delta) => {
-                        tracing::debug!(
-                            "sorted set remove [{}], value [{}], delta [{}]",
-                            key,
-                            value,
-                            delta
-                        );
-
-                        conn.zincr(key, value, *delta).or_restart()?;
-
-                        // removal of dangling scores  (aka garbage collection)
-                        conn.zrembyscore(key, 0, 0).or_restart()?;
-                    }
-                    CRDTCommand::AnyWriteWins(key, value) => {
-                        tracing::debug!("overwrite [{}]", key);
-
-                        conn.set(key, value).or_restart()?;
-                    }
-                    CRDTCommand::PNCounter(key, value) => {
-                        tracing::debug!("increasing counter [{}], by [{}]", key, value);
-
-                        conn.incr(key, *value).or_restart()?;
-                    }
-                    CRDTCommand::HashSetValue(key, member, value) => {
-                        tracing::debug!("setting hash key {} member {}", key, member);
-
-                        conn.hset(key, member, value).or_restart()?;
-                    }
-                    CRDTCommand::HashCounter(key, member, delta) => {
-                        tracing::debug!(
-                            "increasing hash key {} member {} by {}",
-                            key,
-                            member,
-                            delta
-                        );
-
-                        conn.hincr(key, member, *delta).or_restart()?;
-                    }
-                    CRDTCommand::HashUnsetKey(key, member) => {
-                        tracing::debug!("deleting hash key {} member {}", key, member);
-
-                        conn.hdel(member, key).or_restart()?;
-                    }
-                    CRDTCommand::BlockFinished(point) => {
-                        if let Point::Specific(slot, _hash) = point {
-                            // End redis transaction
-                            redis::cmd("EXEC").query(conn.deref_mut()).or_restart()?;
-
-                            stage.ops_count.inc(1);
-                            stage.latest_block.set(*slot as i64);
-                        }
+                        stage.cursor.rollback_to(point);
                     }
+                    x => self.pending.push(x.clone()),
                 };
             }
             _ => {}
@@ -195,14 +518,51 @@ pub enum StreamStrategy {
     ByBlock,
 }
 
+/// How `Value::Json` values are written
+#[derive(Debug, Clone, Default, Deserialize)]
+pub enum JsonEncoding {
+    /// serialized as a JSON string
+    #[default]
+    String,
+    /// stored as native documents through the RedisJSON module (`JSON.SET`)
+    /// when written to a whole key, falling back to a JSON string for hash
+    /// and set members
+    RedisJson,
+}
+
 #[derive(Default, Debug, Deserialize)]
 pub struct Config {
     pub url: String,
     pub stream_name: Option<String>,
     pub stream_max_length: Option<usize>,
+    pub cursor_key: Option<String>,
+    pub journal_name: Option<String>,
+    /// amount of blocks that can be rolled back, older journal entries are
+    /// pruned
+    pub security_param: Option<usize>,
+    #[serde(default)]
+    pub json_encoding: JsonEncoding,
 }
 
 impl Config {
+    fn cursor_key(&self) -> String {
+        self.cursor_key
+            .clone()
+            .unwrap_or(String::from("scrolls-cursor"))
+    }
+
+    pub fn load_cursor(&self) -> Result<Cursor, Error> {
+        let client = redis::Client::open(self.url.as_str()).map_err(Error::storage)?;
+        let mut conn = client.get_connection().map_err(Error::storage)?;
+
+        let value: Option<String> = conn.get(self.cursor_key()).map_err(Error::storage)?;
+
+        match value {
+            Some(json) => Cursor::from_json(&json),
+            None => Ok(Cursor::new(Default::default())),
+        }
+    }
+
     pub fn bootstrapper(self, ctx: &Context) -> Result<Stage, Error> {
         let stage = Stage {
             input: Default::default(),
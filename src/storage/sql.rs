@@ -0,0 +1,197 @@
+//! SQL generation shared by the RDBMS storage backends, which only differ in
+//! how statements are bound and run by their drivers.
+//!
+//! Backends buffer the commands of a block until it's finished and then run
+//! them in a single transaction that also persists the breadcrumbs, so a
+//! failed block is rolled back and replayed as a whole on retry.
+
+use std::borrow::Cow;
+
+use crate::framework::*;
+
+pub const CURSOR_TABLE_DDL: &str =
+    "CREATE TABLE IF NOT EXISTS scrolls_cursor (id INTEGER PRIMARY KEY, breadcrumbs TEXT NOT NULL)";
+
+pub const CURSOR_SELECT: &str = "SELECT breadcrumbs FROM scrolls_cursor WHERE id = 0";
+
+/// Statement to run within the transaction of a block
+pub enum Statement<'a> {
+    /// raw SQL, possibly holding several statements, run without params
+    Batch(&'a str),
+    /// a single statement bound to the given params
+    Prepared(Cow<'a, str>, Vec<&'a SqlParam>),
+}
+
+/// How a column is updated when the upserted row already exists
+enum Update {
+    Overwrite,
+    Increment,
+}
+
+#[derive(Clone, Copy)]
+pub enum Dialect {
+    Postgres,
+    Sqlite,
+    Mysql,
+}
+
+impl Dialect {
+    fn quote(&self, ident: &str) -> String {
+        let (open, close) = match self {
+            Dialect::Postgres | Dialect::Sqlite => ('"', "\"\""),
+            Dialect::Mysql => ('`', "``"),
+        };
+
+        // schema-qualified names are quoted part by part
+        ident
+            .split('.')
+            .map(|x| format!("{0}{1}{0}", open, x.replace(open, close)))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    /// Placeholder of the 1-based position of a param
+    fn placeholder(&self, position: usize) -> String {
+        match self {
+            Dialect::Postgres => format!("${}", position),
+            Dialect::Sqlite => format!("?{}", position),
+            Dialect::Mysql => String::from("?"),
+        }
+    }
+
+    fn placeholders(&self, count: usize) -> String {
+        (1..=count)
+            .map(|i| self.placeholder(i))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Clause that handles a conflict on the key columns by applying the
+    /// updates to the existing row, or by keeping it if there are none.
+    /// In Postgres the target table is aliased as `existing` so that the
+    /// columns of the row already stored can be told apart from the values
+    /// being inserted, which are referenced through `EXCLUDED`.
+    fn on_conflict(&self, key: &[&str], updates: &[(&str, Update)]) -> String {
+        let updates: Vec<_> = updates
+            .iter()
+            .map(|(column, update)| {
+                let column = self.quote(column);
+
+                let excluded = match self {
+                    Dialect::Postgres => format!("EXCLUDED.{}", column),
+                    Dialect::Sqlite => format!("excluded.{}", column),
+                    Dialect::Mysql => format!("VALUES({})", column),
+                };
+
+                match (update, self) {
+                    (Update::Overwrite, _) => format!("{} = {}", column, excluded),
+                    (Update::Increment, Dialect::Postgres) => {
+                        format!("{0} = existing.{0} + {1}", column, excluded)
+                    }
+                    (Update::Increment, _) => format!("{0} = {0} + {1}", column, excluded),
+                }
+            })
+            .collect();
+
+        let key: Vec<_> = key.iter().map(|x| self.quote(x)).collect();
+
+        match (self, updates.is_empty()) {
+            // conflicts are detected on any unique index, which is expected
+            // to be the one over the key columns. A no-op update keeps the
+            // existing row without ignoring other errors.
+            (Dialect::Mysql, true) => format!(
+                "ON DUPLICATE KEY UPDATE {}",
+                key.iter()
+                    .map(|x| format!("{0} = {0}", x))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            (Dialect::Mysql, false) => format!("ON DUPLICATE KEY UPDATE {}", updates.join(", ")),
+            (_, true) => format!("ON CONFLICT ({}) DO NOTHING", key.join(", ")),
+            (_, false) => format!(
+                "ON CONFLICT ({}) DO UPDATE SET {}",
+                key.join(", "),
+                updates.join(", ")
+            ),
+        }
+    }
+
+    /// Writes the breadcrumbs, bound to the single param, into the cursor row
+    pub fn cursor_upsert(&self) -> String {
+        format!(
+            "INSERT INTO scrolls_cursor (id, breadcrumbs) VALUES (0, {}) {}",
+            self.placeholder(1),
+            self.on_conflict(&["id"], &[("breadcrumbs", Update::Overwrite)])
+        )
+    }
+
+    fn upsert_sql(
+        &self,
+        table: &str,
+        key: &[Column],
+        values: &[(Column, SqlParam)],
+        increment: &[Column],
+    ) -> String {
+        let columns: Vec<_> = values.iter().map(|(x, _)| self.quote(x)).collect();
+
+        let updates: Vec<_> = values
+            .iter()
+            .map(|(x, _)| x)
+            .filter(|x| !key.contains(x))
+            .map(|x| match increment.contains(x) {
+                true => (x.as_str(), Update::Increment),
+                false => (x.as_str(), Update::Overwrite),
+            })
+            .collect();
+
+        let key: Vec<_> = key.iter().map(String::as_str).collect();
+
+        let alias = match self {
+            Dialect::Postgres => " AS existing",
+            _ => "",
+        };
+
+        format!(
+            "INSERT INTO {}{} ({}) VALUES ({}) {}",
+            self.quote(table),
+            alias,
+            columns.join(", "),
+            self.placeholders(values.len()),
+            self.on_conflict(&key, &updates)
+        )
+    }
+
+    fn delete_sql(&self, table: &str, filter: &[(Column, SqlParam)]) -> String {
+        let conditions: Vec<_> = filter
+            .iter()
+            .enumerate()
+            .map(|(i, (x, _))| format!("{} = {}", self.quote(x), self.placeholder(i + 1)))
+            .collect();
+
+        format!(
+            "DELETE FROM {} WHERE {}",
+            self.quote(table),
+            conditions.join(" AND ")
+        )
+    }
+
+    /// Translates a command into the statement that runs it, if any
+    pub fn statement<'a>(&self, command: &'a RDBMSCommand) -> Option<Statement<'a>> {
+        match command {
+            RDBMSCommand::ExecuteSQL(sql) => Some(Statement::Batch(sql)),
+            RDBMSCommand::ExecuteParameterizedSQL(sql, params) => Some(Statement::Prepared(
+                Cow::Borrowed(sql.as_str()),
+                params.iter().collect(),
+            )),
+            RDBMSCommand::Upsert(table, key, values, increment) => Some(Statement::Prepared(
+                Cow::Owned(self.upsert_sql(table, key, values, increment)),
+                values.iter().map(|(_, x)| x).collect(),
+            )),
+            RDBMSCommand::Delete(table, filter) => Some(Statement::Prepared(
+                Cow::Owned(self.delete_sql(table, filter)),
+                filter.iter().map(|(_, x)| x).collect(),
+            )),
+            RDBMSCommand::BlockStarting(..) | RDBMSCommand::BlockFinished(..) => None,
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use gasket::framework::*;
+use pallas::network::miniprotocols::Point;
+use rusqlite::types::{Null, ToSqlOutput};
+use rusqlite::{params_from_iter, Connection, OptionalExtension, ToSql};
+use serde::Deserialize;
+use std::path::PathBuf;
+
+use super::sql::{Dialect, Statement, CURSOR_SELECT, CURSOR_TABLE_DDL};
+use crate::framework::*;
+
+const DIALECT: Dialect = Dialect::Sqlite;
+
+impl ToSql for SqlParam {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        match self {
+            SqlParam::Null => Ok(ToSqlOutput::from(Null)),
+            SqlParam::Text(x) => x.to_sql(),
+            SqlParam::BigInt(x) => x.to_sql(),
+            SqlParam::Bytea(x) => x.to_sql(),
+            // sqlite has no dedicated types for these, stored as text
+            SqlParam::Json(x) => Ok(ToSqlOutput::from(x.to_string())),
+            SqlParam::Numeric(x) => Ok(ToSqlOutput::from(x.to_string())),
+        }
+    }
+}
+
+pub struct Worker {
+    conn: Connection,
+    pending: Vec<RDBMSCommand>,
+}
+
+#[async_trait::async_trait(?Send)]
+impl super::BlockStore for Worker {
+    fn clear_pending(&mut self) {
+        self.pending.clear();
+    }
+
+    async fn commit_block(
+        &mut self,
+        point: &Point,
+        direction: BlockDirection,
+        cursor: &Cursor,
+    ) -> Result<(), WorkerError> {
+        // failures are retried rather than restarting the worker, so that the
+        // same block is committed again from the buffered commands. The
+        // transaction is rolled back when dropped without committing.
+        let tx = self.conn.transaction().or_retry()?;
+
+        for statement in self.pending.iter().filter_map(|x| DIALECT.statement(x)) {
+            match statement {
+                Statement::Batch(sql) => {
+                    tx.execute_batch(sql).or_retry()?;
+                }
+                Statement::Prepared(sql, params) => {
+                    tx.execute(&sql, params_from_iter(params)).or_retry()?;
+                }
+            }
+        }
+
+        let cursor = cursor.fork();
+        cursor.track_block(point.clone(), direction);
+        tx.execute(&DIALECT.cursor_upsert(), [cursor.to_json()])
+            .or_retry()?;
+
+        tx.commit().or_retry()?;
+
+        self.pending.clear();
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl gasket::framework::Worker<Stage> for Worker {
+    async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
+        let conn = Connection::open(&stage.path).or_panic()?;
+        conn.execute(CURSOR_TABLE_DDL, []).or_restart()?;
+
+        Ok(Self {
+            conn,
+            pending: vec![],
+        })
+    }
+
+    async fn schedule(
+        &mut self,
+        stage: &mut Stage,
+    ) -> Result<WorkSchedule<StorageEvent>, WorkerError> {
+        let msg = stage.input.recv().await.or_panic()?;
+        Ok(WorkSchedule::Unit(msg.payload))
+    }
+
+    async fn execute(
+        &mut self,
+        event: &StorageEvent,
+        stage: &mut Stage,
+    ) -> Result<(), WorkerError> {
+        match event {
+            StorageEvent::RDBMS(rdbms_command) => {
+                match rdbms_command {
+                    RDBMSCommand::BlockStarting(..) => {
+                        self.pending.clear();
+                    }
+                    RDBMSCommand::BlockFinished(point, direction) => {
+                        super::finish_block(
+                            self,
+                            point,
+                            *direction,
+                            &stage.cursor,
+                            &stage.ops_count,
+                            &stage.latest_block,
+                        )
+                        .await?;
+                    }
+                    x => self.pending.push(x.clone()),
+                };
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Stage)]
+#[stage(name = "storage-sqlite", unit = "StorageEvent", worker = "Worker")]
+pub struct Stage {
+    path: PathBuf,
+
+    pub input: StorageInputPort,
+
+    cursor: Cursor,
+
+    #[metric]
+    ops_count: gasket::metrics::Counter,
+
+    #[metric]
+    latest_block: gasket::metrics::Gauge,
+}
+
+#[derive(Default, Debug, Deserialize)]
+pub struct Config {
+    pub path: String,
+}
+
+impl Config {
+    pub fn load_cursor(&self) -> Result<Cursor, Error> {
+        let conn = Connection::open(&self.path).map_err(Error::storage)?;
+
+        conn.execute(CURSOR_TABLE_DDL, []).map_err(Error::storage)?;
+
+        let breadcrumbs: Option<String> = conn
+            .query_row(CURSOR_SELECT, [], |row| row.get(0))
+            .optional()
+            .map_err(Error::storage)?;
+
+        match breadcrumbs {
+            Some(json) => Cursor::from_json(&json),
+            None => Ok(Cursor::new(Default::default())),
+        }
+    }
+
+    pub fn bootstrapper(self, ctx: &Context) -> Result<Stage, Error> {
+        let stage = Stage {
+            input: Default::default(),
+            cursor: ctx.cursor.clone(),
+            path: PathBuf::from(self.path),
+            ops_count: Default::default(),
+            latest_block: Default::default(),
+        };
+
+        Ok(stage)
+    }
+}